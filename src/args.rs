@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -7,17 +8,67 @@ pub struct Cli {
     #[arg(short, long, global = true, help = "Custom path to the codex file")]
     pub path: Option<PathBuf>,
 
+    /// Named vault from the config file, see `hermes vault list`
+    #[arg(long, global = true, conflicts_with = "path", verbatim_doc_comment)]
+    pub vault: Option<String>,
+
+    /// Refuse every command that changes the codex, also HERMES_READ_ONLY=1.
+    #[arg(long, global = true, verbatim_doc_comment)]
+    pub read_only: bool,
+
+    /// Use a codex or backup that group or others can access.
+    #[arg(long, global = true, verbatim_doc_comment)]
+    pub allow_insecure_permissions: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
-#[derive(ValueEnum, Clone, Debug, Default)]
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     #[default]
     Table,
     Json,
 }
 
+/// Columns of the `ls` table. Issuer and account are added when a record
+/// has them, previous during a rotation.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Alias,
+    Issuer,
+    Account,
+    Tags,
+    Otp,
+    Previous,
+    Created,
+    Rem,
+    Notes,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConflictStrategy {
+    /// Keep the record changed last
+    #[default]
+    Newest,
+    /// Keep the record of this codex
+    Ours,
+    /// Keep the record of the other codex
+    Theirs,
+    /// Ask for every conflict
+    Interactive,
+}
+
+/// Record order written by `compact`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum SortBy {
+    #[default]
+    Alias,
+    Created,
+}
+
 #[derive(clap::Args)]
 pub struct EncryptArgs {
     /// WARNING: Store the secret in plain text. Use for debugging only.
@@ -28,14 +79,72 @@ pub struct EncryptArgs {
     pub password: Option<String>,
 }
 
+#[derive(clap::Args)]
+pub struct LabelArgs {
+    /// Service of the record, e.g. GitHub. Taken from an otpauth label when omitted,
+    /// an empty value clears it.
+    #[clap(long, verbatim_doc_comment)]
+    pub issuer: Option<String>,
+    /// Account at the issuer, e.g. me@example.com. Taken from an otpauth label too.
+    #[clap(long, verbatim_doc_comment)]
+    pub account: Option<String>,
+}
+
+impl LabelArgs {
+    pub fn is_given(&self) -> bool {
+        self.issuer.is_some() || self.account.is_some()
+    }
+}
+
+#[derive(clap::Args)]
+pub struct TagFilter {
+    /// Only records with this tag, repeatable. All tags must match unless --any-tag.
+    #[clap(long = "tag", value_name = "TAG", verbatim_doc_comment)]
+    pub tags: Vec<String>,
+    /// Match records with at least one of the --tag values
+    #[clap(long, requires = "tags", verbatim_doc_comment)]
+    pub any_tag: bool,
+}
+
+#[derive(clap::Args)]
+pub struct RotationArgs {
+    /// Keep showing the outgoing code for HOURS, default HERMES_GRACE_HOURS
+    #[clap(long, value_name = "HOURS", verbatim_doc_comment)]
+    pub grace: Option<u64>,
+    /// Keep showing the outgoing code until `hermes confirm`
+    #[clap(long, conflicts_with = "grace", verbatim_doc_comment)]
+    pub until_confirmed: bool,
+}
+
+#[derive(clap::Args)]
+pub struct PasswordArgs {
+    /// WARNING: Using this flag leaves password in shell history.
+    #[clap(long, verbatim_doc_comment)]
+    pub password: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct CompareArgs {
+    /// Codex to compare with, in any format or backend
+    pub other: PathBuf,
+    /// Password of the other codex, defaults to the password of this one
+    #[clap(long, verbatim_doc_comment)]
+    pub other_password: Option<String>,
+    #[clap(flatten)]
+    pub auth: PasswordArgs,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Adds code to the hermes
     Add {
         #[clap(short = 'a', long)]
         alias: String,
-        #[clap(short = 'c', long)]
-        code: String,
+        /// Secret code or otpauth:// URI. Prompted for (or read from stdin) when omitted.
+        #[clap(short = 'c', long, verbatim_doc_comment)]
+        code: Option<String>,
+        #[clap(flatten)]
+        label: LabelArgs,
         #[clap(flatten)]
         encryption: EncryptArgs,
     },
@@ -43,34 +152,324 @@ pub enum Commands {
     Remove {
         #[clap(short = 'a', long)]
         alias: String,
+        /// Delete the record instead of moving it to the trash
+        #[clap(long, verbatim_doc_comment)]
+        permanent: bool,
+        #[clap(flatten)]
+        auth: PasswordArgs,
     },
     /// Update code by alias
     Update {
         #[clap(short = 'a', long)]
         alias: String,
-        #[clap(short = 'c', long)]
-        code: String,
+        /// Secret code or otpauth:// URI. Prompted for (or read from stdin) when
+        /// omitted, unless only --issuer or --account change.
+        #[clap(short = 'c', long, verbatim_doc_comment)]
+        code: Option<String>,
+        #[clap(flatten)]
+        label: LabelArgs,
+        #[clap(flatten)]
+        rotation: RotationArgs,
         #[clap(flatten)]
         encryption: EncryptArgs,
     },
+    /// Drop the outgoing secret kept by `update --grace`
+    Confirm {
+        alias: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// List previous secrets of a record
+    History {
+        alias: String,
+        /// Drop every previous secret of the record
+        #[clap(long, verbatim_doc_comment)]
+        purge: bool,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Restore a previous secret of a record
+    Rollback {
+        alias: String,
+        /// Version from `hermes history`, default 1 (the last secret replaced)
+        #[clap(long, value_name = "N", verbatim_doc_comment)]
+        to: Option<usize>,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Lock a record against remove, update and rename, or the whole codex
+    Lock {
+        /// Record to lock, the whole codex (read-only) when omitted
+        alias: Option<String>,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Unlock a record, or the whole codex
+    Unlock {
+        /// Record to unlock, the whole codex when omitted
+        alias: Option<String>,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
     /// Rename alias
     Rename {
         old_alias: String,
         new_alias: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
     },
     /// Get codes for all/alias records
     Ls {
         #[clap(short = 'a', long)]
         alias: Option<String>,
-        #[clap(short, long)]
+        /// Only print the code, also HERMES_QUIET=1 or `quiet` in the config
+        #[clap(short, long, verbatim_doc_comment)]
         quiet: bool,
-        #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Table)]
-        format: OutputFormat,
+        /// Default table, also HERMES_FORMAT or `format` in the config
+        #[arg(short = 'f', long, value_enum, verbatim_doc_comment)]
+        format: Option<OutputFormat>,
+        /// Table columns, default alias,otp,rem, also HERMES_COLUMNS or `columns` in the config
+        #[arg(long, value_enum, value_delimiter = ',', verbatim_doc_comment)]
+        columns: Option<Vec<Column>>,
+        /// Search every vault of the config file
+        #[clap(long, verbatim_doc_comment)]
+        all_vaults: bool,
+        #[clap(flatten)]
+        tags: TagFilter,
+        /// Show the notes of the records, same as adding the notes column
+        #[clap(long, verbatim_doc_comment)]
+        show_notes: bool,
         #[clap(flatten)]
         encryption: EncryptArgs,
     },
-    /// Show location of codex file
-    Config {},
-    /// Migrate legacy codex format to JSON
-    Migrate,
+    /// Set, show or clear the encrypted note of a record
+    Note {
+        #[command(subcommand)]
+        action: NoteAction,
+    },
+    /// Add or remove a tag of a record
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// List tags with the number of records carrying them
+    Tags,
+    /// Show location of codex file, or manage the config file
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Migrate codex to the current format version
+    Migrate {
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Rewrite the codex canonically: sorted JSON lines, no blanks or duplicates
+    Compact {
+        /// Record order
+        #[arg(long, value_enum, default_value_t = SortBy::Alias)]
+        sort: SortBy,
+        /// Only show what would change
+        #[clap(long, verbatim_doc_comment)]
+        dry_run: bool,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Seal the codex with a MAC, or re-seal it after a manual edit
+    Seal {
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Record code displays and changes in a hash-chained log. It only detects
+    /// accidental edits: whoever can write the log can recompute the chain.
+    #[clap(verbatim_doc_comment)]
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Manage git history of the codex
+    Git {
+        #[command(subcommand)]
+        action: GitAction,
+    },
+    /// Pull, merge and push the codex against the git remote
+    Sync {
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Show records added, removed or changed in another codex
+    Diff {
+        #[clap(flatten)]
+        compare: CompareArgs,
+    },
+    /// Merge the records of another codex into this one
+    Merge {
+        #[clap(flatten)]
+        compare: CompareArgs,
+        /// How to resolve records changed on both sides
+        #[arg(short, long, value_enum, default_value_t = ConflictStrategy::Newest)]
+        strategy: ConflictStrategy,
+    },
+    /// Copy the codex into another storage backend
+    Convert {
+        /// New codex; .db/.sqlite/.sqlite3 => SQLite, anything else => text file
+        #[clap(verbatim_doc_comment)]
+        dest: PathBuf,
+    },
+    /// Revert the last add, remove, update or rename
+    Undo {
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Apply the last undone operation again
+    Redo {
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Move unparseable lines to <codex>.quarantine
+    Repair {
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// List, restore or purge removed records
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+    /// Manage named vaults
+    Vault {
+        #[command(subcommand)]
+        action: VaultAction,
+    },
+    /// Manage codex backups
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// List backups with their record counts
+    List,
+    /// Replace the codex with a backup
+    Restore { id: String },
+    /// Remove old backups
+    Prune {
+        /// Number of backups to keep (default: HERMES_BACKUP_RETENTION or 10)
+        #[clap(long, verbatim_doc_comment)]
+        keep: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// List removed records, newest first
+    List,
+    /// Move a record back into the codex, renamed if its alias is taken
+    Restore {
+        alias: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Delete trashed records for good
+    Purge {
+        /// Only records removed more than this many days ago
+        #[clap(long, value_name = "DAYS", verbatim_doc_comment)]
+        older_than: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TagAction {
+    /// Tag a record, e.g. `hermes tag add github infra`
+    Add {
+        alias: String,
+        tag: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Remove a tag from a record
+    Remove {
+        alias: String,
+        tag: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NoteAction {
+    /// Replace the note of a record
+    Set {
+        alias: String,
+        /// Note text. Read from stdin, or prompted for, when omitted.
+        #[clap(verbatim_doc_comment)]
+        text: Option<String>,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Print the note of a record
+    Show {
+        alias: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+    /// Remove the note of a record
+    Clear {
+        alias: String,
+        #[clap(flatten)]
+        auth: PasswordArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print a setting, or the whole config file without key
+    Get { key: Option<String> },
+    /// Change a setting, e.g. `hermes config set format json`
+    Set { key: String, value: String },
+    /// Remove a setting, so the built-in default applies again
+    Unset { key: String },
+    /// Open the config file in $VISUAL or $EDITOR
+    Edit,
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// Start logging every code display and change of the codex
+    Enable,
+    /// Check that no entry was accidentally edited, removed or cut off
+    Verify,
+    /// List logged entries, oldest first
+    Show {
+        /// Only entries for this alias
+        #[clap(short = 'a', long)]
+        alias: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GitAction {
+    /// Commit every change of the codex into a git repository in its directory
+    Init {
+        /// Remote to sync with, e.g. a bare repository
+        #[clap(long)]
+        remote: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VaultAction {
+    /// List configured vaults
+    List,
+    /// Register a codex under a name
+    Add { name: String, path: PathBuf },
+    /// Forget a vault, its codex is left on disk
+    Remove { name: String },
+    /// Use a vault when neither --path nor --vault is given
+    Default { name: String },
 }
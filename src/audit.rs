@@ -0,0 +1,187 @@
+use crate::file;
+use crate::models;
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+// prev of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One audited access or change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    pub seq: u64,
+    pub at: u64, // Unix timestamp in sec
+    pub user: String,
+    pub host: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Hash of the entry before, GENESIS for the first one
+    pub prev: String,
+}
+
+/// A line of `<codex>.audit`: the event and the hash chaining it to the
+/// entry before. The hash is unkeyed, so it only detects accidental edits.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub event: Event,
+    pub hash: String,
+}
+
+impl Event {
+    fn hash(&self) -> String {
+        let json = serde_json::to_string(self).expect("events always serialize");
+        HEXLOWER.encode(&Sha256::digest(json.as_bytes()))
+    }
+}
+
+pub fn audit_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".audit");
+    PathBuf::from(name)
+}
+
+// seq and hash of the last entry, written after every append so that a cut
+// off tail shows up in verify
+fn head_path(path: &Path) -> PathBuf {
+    let mut name = audit_path(path).into_os_string();
+    name.push(".head");
+    PathBuf::from(name)
+}
+
+/// Auditing is on once `hermes audit enable` created the log.
+pub fn is_enabled(path: &Path) -> bool {
+    audit_path(path).exists()
+}
+
+/// Starts the log of the codex at path, false if it already exists.
+pub fn enable(path: &Path) -> Result<bool, String> {
+    if is_enabled(path) {
+        return Ok(false);
+    }
+    append(path, "audit enable", &[])?;
+    Ok(true)
+}
+
+/// Appends one entry per alias, or a single one without alias, when
+/// auditing is enabled for the codex at path.
+pub fn record(path: &Path, command: &str, aliases: &[&str]) -> Result<(), String> {
+    if !is_enabled(path) {
+        return Ok(());
+    }
+    append(path, command, aliases)
+}
+
+fn append(path: &Path, command: &str, aliases: &[&str]) -> Result<(), String> {
+    let log = audit_path(path);
+    // readers of the codex only hold a shared lock, the log needs its own
+    let _lock = file::lock_exclusive(&log).map_err(|e| e.to_string())?;
+
+    let last = load(path)?.pop();
+    let (mut seq, mut prev) = match last {
+        Some(entry) => (entry.event.seq + 1, entry.hash),
+        None => (0, GENESIS.to_string()),
+    };
+
+    let at = models::now();
+    let (user, host) = (current_user(), current_host());
+    let aliases: Vec<Option<String>> = match aliases {
+        [] => vec![None],
+        aliases => aliases.iter().map(|a| Some(a.to_string())).collect(),
+    };
+
+    let mut content = String::new();
+    for alias in aliases {
+        let event = Event {
+            seq,
+            at,
+            user: user.clone(),
+            host: host.clone(),
+            command: command.to_string(),
+            alias,
+            prev,
+        };
+        let hash = event.hash();
+        content.push_str(&serde_json::to_string(&AuditEntry { event, hash: hash.clone() })
+            .map_err(|e| e.to_string())?);
+        content.push('\n');
+        (seq, prev) = (seq + 1, hash);
+    }
+
+    file::append_file(&log, content.as_bytes())
+        .and_then(|_| file::overwrite_file(&head_path(path), format!("{} {prev}\n", seq - 1).as_bytes()))
+        .map_err(|e| format!("Failed to write audit log: {e}"))
+}
+
+/// Reads the audit log of the codex at path, a missing log is empty.
+pub fn load(path: &Path) -> Result<Vec<AuditEntry>, String> {
+    let log = audit_path(path);
+    let content = match std::fs::read_to_string(&log) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read audit log {}: {e}", log.display())),
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, l)| {
+            serde_json::from_str(l)
+                .map_err(|e| format!("Audit log line {} is unreadable: {e}", i + 1))
+        })
+        .collect()
+}
+
+/// Walks the hash chain and returns the number of entries. Fails on the
+/// first edited, inserted or missing entry, and when the tail was cut off.
+pub fn verify(path: &Path) -> Result<usize, String> {
+    if !is_enabled(path) {
+        return Err("Auditing is not enabled, see `hermes audit enable`.".into());
+    }
+
+    let entries = load(path)?;
+    let mut prev = GENESIS.to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        let line = i + 1;
+        if entry.event.seq != i as u64 {
+            return Err(format!("Audit log line {line}: expected entry {i}, found {}.", entry.event.seq));
+        }
+        if entry.event.prev != prev {
+            return Err(format!("Audit log line {line}: does not follow the entry before."));
+        }
+        if entry.event.hash() != entry.hash {
+            return Err(format!("Audit log line {line}: content does not match its hash."));
+        }
+        prev = entry.hash.clone();
+    }
+
+    let head = std::fs::read_to_string(head_path(path))
+        .map_err(|e| format!("Failed to read audit head: {e}"))?;
+    let expected = match entries.last() {
+        Some(last) => format!("{} {}", last.event.seq, last.hash),
+        None => String::new(),
+    };
+    if head.trim() != expected {
+        return Err(format!("Audit log ends after {} entries, but not where its head says: \
+            entries were cut off.", entries.len()));
+    }
+    Ok(entries.len())
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+fn current_host() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".into())
+}
@@ -1,11 +1,30 @@
-use crate::args::OutputFormat;
+use crate::args::{Column, ConflictStrategy, EncryptArgs, LabelArgs, OutputFormat, RotationArgs, SortBy, TagFilter};
+use crate::audit;
+use crate::compact;
+use crate::config::{self, Config, VaultEntry};
+use crate::error::Failure;
 use crate::file;
-use crate::models::Record;
+use crate::git;
+use crate::integrity;
+use crate::journal::{Entry, Journal, Op};
+use crate::merge::{self, Change};
+use crate::migrations;
+use crate::models::{self, Codex, Header, Note, PreviousSecret, Record, CODEX_VERSION};
+use crate::storage;
+use crate::trash::{self, Trashed};
+use crate::vault::Vault;
 use crate::otp;
 use crate::ui;
 use data_encoding::BASE32_NOPAD;
-use std::io;
-use std::path::{PathBuf, Path};
+use std::io::{self, IsTerminal, Read};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const HISTORY_LIMIT: usize = 10;
+/// Columns of the `ls` table unless configured otherwise.
+pub const DEFAULT_COLUMNS: [Column; 3] = [Column::Alias, Column::Otp, Column::Rem];
+const INTEGRITY_ERROR: &str = "Integrity check failed: the codex was modified outside hermes \
+or the password is wrong. Run `hermes seal` after a deliberate manual edit.";
 
 fn sanitize_and_validate_code(code: &str) -> Result<String, String> {
     let clean = code.to_uppercase().replace("=", "");
@@ -16,14 +35,64 @@ fn sanitize_and_validate_code(code: &str) -> Result<String, String> {
     Ok(clean)
 }
 
+// priority 1 => --password
+// priority 2 => env var HERMES_PASSWORD
+// priority 3 => password_command of the config file
+// priority 4 => interactive prompt without echo
 fn get_effective_password(password: &Option<String>) -> String {
     password
         .clone()
         .or_else(|| std::env::var("HERMES_PASSWORD").ok())
+        .or_else(password_from_command)
         .unwrap_or_else(|| rpassword::prompt_password("Enter password: ")
             .expect("Failed to read password"))
 }
 
+// a failing password_command falls back to the prompt
+fn password_from_command() -> Option<String> {
+    let command = Config::load().ok()?.password_command?;
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stderr(std::process::Stdio::inherit())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => {
+            Some(String::from_utf8_lossy(&o.stdout).trim_end_matches(['\n', '\r']).to_string())
+        }
+        _ => {
+            eprintln!("Warning: password_command failed, asking for the password instead.");
+            None
+        }
+    }
+}
+
+// priority 1 => -c/--code
+// priority 2 => stdin, when it is piped
+// priority 3 => interactive prompt without echo
+pub fn get_effective_code(code: &Option<String>) -> Result<String, String> {
+    if let Some(c) = code {
+        return Ok(c.clone());
+    }
+
+    let stdin = io::stdin();
+    let input = if stdin.is_terminal() {
+        rpassword::prompt_password("Enter code: ")
+            .map_err(|e| format!("Failed to read code: {e}"))?
+    } else {
+        let mut buf = String::new();
+        stdin.lock().read_to_string(&mut buf)
+            .map_err(|e| format!("Failed to read code from stdin: {e}"))?;
+        buf
+    };
+
+    let input = input.trim().to_string();
+    if input.is_empty() {
+        return Err("Error: No code provided.".into());
+    }
+    Ok(input)
+}
+
 /* Validate code - check if it is a valid base32
 * Here I beleive it is necessary to add some explanation for base32 and TOTP.
 * Overtime I forgot what it does and my code comments are not good :/
@@ -57,41 +126,43 @@ pub fn add(
     path: &Path,
     alias: &str,
     code: &str,
+    label: &LabelArgs,
     is_unencrypt: &bool,
     password: &Option<String>,
-) -> Result<(), String> {
-    let clean_code = sanitize_and_validate_code(code)?;
-
-    // for Legacy file format
-    if alias.contains(':') {
-        return Err("Error: Alias cannot contain ':'".into());
-    }
-
-    if file::file_exists(path) && file::alias_exists(alias, path) {
-        return Err(format!("Error: Alias '{alias}' already exists."));
-    }
+) -> Result<(), Failure> {
+    let (clean_code, found) = parse_code(code)?;
+    // an alias in otpauth label form names issuer and account too
+    let found = match found {
+        Some(found) => found,
+        None if alias.contains(':') => otp::split_label(alias),
+        None => otp::Label::default(),
+    };
 
     // encrypt if necessary
-    let secret = if *is_unencrypt {
-        clean_code.to_string()
-    } else {
-        otp::encrypt(&clean_code.to_string(), &get_effective_password(password))
+    let password = resolve_password(is_unencrypt, password);
+    let secret = match &password {
+        Some(pass) if !*is_unencrypt => otp::encrypt(&clean_code, pass),
+        _ => clean_code.clone(),
     };
 
-    // serialize and save
-    let record = Record::new(alias.to_string(), secret.to_string(), *is_unencrypt);
-    let json_data = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+    let mut record = Record::new(alias.to_string(), secret, *is_unencrypt);
+    apply_label(&mut record, label, found);
 
     file::ensure_dir_exists(path).map_err(|e| e.to_string())?;
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
 
-    if file::file_exists(path) {
-        file::create_routine_backup(path)
-            .map_err(|e| format!("Warning: Backup failed: {}", e))?;
-        file::append_to_file(path, &json_data).map_err(|e| e.to_string())?;
-    } else {
-        file::overwrite_file(path, &json_data)
-            .map_err(|e| e.to_string())?;
+    let mut vault = Vault::open(path)?;
+    ensure_writable(&vault)?;
+    let seal_key = unlock_codex(vault.codex(), &password)?;
+
+    // a brand new (or empty) codex starts at the current format version
+    if vault.was_empty() {
+        vault.replace(Codex { header: Some(Header::current()), lines: Vec::new() });
     }
+    check_alias(vault.codex(), alias)?;
+    let entry = Entry::new(Op::Add, None, Some(record.clone()));
+    vault.insert(record)?;
+    commit_op(&mut vault, path, seal_key.as_deref(), entry)?;
 
     println!("Record saved.");
 
@@ -103,82 +174,501 @@ pub fn add(
     Ok(())
 }
 
+/// Fails when alias is taken, before add prompts for code or password.
+/// add checks again under the lock.
+pub fn ensure_new_alias(path: &Path, alias: &str) -> Result<(), String> {
+    if file::file_exists(path) && storage::open(path)?.get(alias)?.is_some() {
+        return Err(format!("Error: Alias '{alias}' already exists."));
+    }
+    Ok(())
+}
+
+// legacy lines are split at ':', so only a codex that is JSON through and
+// through takes such aliases
+fn check_alias(codex: &Codex, alias: &str) -> Result<(), String> {
+    if alias.contains(':') && !migrations::is_json_only(codex) {
+        return Err("Aliases may only contain ':' once the codex is fully JSON, \
+            run `hermes migrate` first.".into());
+    }
+    Ok(())
+}
+
 pub fn update_code(
     path: &Path,
     alias: &str,
-    new_code: &str,
+    new_code: Option<&str>,
+    label: &LabelArgs,
+    rotation: &RotationArgs,
     is_unencrypt: &bool,
     password: &Option<String>,
-) -> Result<(), String> {
-    let clean_code = sanitize_and_validate_code(new_code)?;
+) -> Result<(), Failure> {
+    let parsed = new_code.map(parse_code).transpose()?;
+
+    // prompt before locking, so readers are not blocked while typing
+    let (password, secret) = match &parsed {
+        Some((clean_code, _)) => {
+            let password = resolve_password(is_unencrypt, password);
+            let secret = match &password {
+                Some(pass) if !*is_unencrypt => otp::encrypt(clean_code, pass),
+                _ => clean_code.clone(),
+            };
+            (password, Some(secret))
+        }
+        None => (password.clone(), None),
+    };
+
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), &password)?;
 
-    // Check if the alias even exists before we do anything else
-    if !file::alias_exists(alias, path) {
-        return Err(format!("No record for '{alias}' found."));
+    let before = vault.get(alias)
+        .cloned()
+        .ok_or_else(|| format!("No record for '{alias}' found."))?;
+    ensure_unlocked(&before)?;
+
+    let mut record = before.clone();
+    let grace = rotation.grace.unwrap_or_else(grace_hours);
+    if let Some(secret) = secret {
+        // a new secret for the same record, the old one goes into history
+        record.replace_secret(secret, *is_unencrypt, history_limit());
+
+        // and stays visible while services may still expect its codes
+        record.previous = match rotation.until_confirmed {
+            true => Some(None),
+            false if grace > 0 => Some(Some(models::now().saturating_add(grace.saturating_mul(3_600)))),
+            false => None,
+        }
+        .map(|until| PreviousSecret {
+            secret: before.secret.clone(),
+            is_unencrypted: before.is_unencrypted,
+            until,
+        });
     }
+    let found = parsed.as_ref().and_then(|(_, found)| found.clone()).unwrap_or_default();
+    apply_label(&mut record, label, found);
+    record.touch();
 
-    // Resolve password once (if needed)
-    let pass = if *is_unencrypt {
-        None
-    } else {
-        Some(get_effective_password(password))
-    };
+    let entry = Entry::new(Op::Update, Some(before), Some(record.clone()));
+    vault.update(alias, record);
+    commit_op(&mut vault, path, seal_key.as_deref(), entry)?;
 
-    // Do the swap
-    remove(path, alias)?;
-    add(path, alias, &clean_code, is_unencrypt, &pass)?;
     println!("Record for '{alias}' successfully updated.");
+    let Some((clean_code, _)) = parsed else {
+        return Ok(());
+    };
+    if rotation.until_confirmed {
+        println!("The previous code stays listed until `hermes confirm {alias}`.");
+    } else if grace > 0 {
+        println!("The previous code stays listed for {grace} hour(s).");
+    }
+
+    match otp::generate_otp(&clean_code) {
+        Ok(code) => println!("{code}"),
+        Err(_) => println!("Error: failed to generate OTP"),
+    }
+
     Ok(())
 }
 
-pub fn remove(path: &Path, alias: &str) -> Result<(), String> {
-    file::create_routine_backup(path)
-        .map_err(|e| format!("Warning: Backup failed: {}", e))?;
+// a Base32 secret, or an otpauth:// URI that brings its label along
+fn parse_code(code: &str) -> Result<(String, Option<otp::Label>), String> {
+    let code = code.trim();
+    if code.starts_with("otpauth://") {
+        let auth = otp::parse_otpauth(code)?;
+        return Ok((sanitize_and_validate_code(&auth.secret)?, Some(auth.label)));
+    }
+    Ok((sanitize_and_validate_code(code)?, None))
+}
+
+// flags win over a label found in the code or alias, an empty flag clears
+fn apply_label(record: &mut Record, flags: &LabelArgs, found: otp::Label) {
+    let pick = |flag: &Option<String>, found: Option<String>, current: Option<String>| match flag {
+        Some(value) => Some(value.trim().to_string()).filter(|v| !v.is_empty()),
+        None => found.or(current),
+    };
+    record.issuer = pick(&flags.issuer, found.issuer, record.issuer.take());
+    record.account = pick(&flags.account, found.account, record.account.take());
+}
+
+// number of previous secrets kept per record
+fn history_limit() -> usize {
+    std::env::var("HERMES_HISTORY_LIMIT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(HISTORY_LIMIT)
+}
 
-    let lines = file::read_file_to_vec(&path)
-        .map_err(|e| e.to_string())?;
-    let original_len = lines.len();
+// hours the outgoing secret stays listed after an update, 0 drops it at once
+fn grace_hours() -> u64 {
+    std::env::var("HERMES_GRACE_HOURS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
 
-    let filtered_lines: Vec<String> = lines.into_iter()
-        .filter(|l| {
-            Record::from_line(l)
-                .map(|r| r.alias != alias)
-                .unwrap_or(true)
-        })
-        .collect();
+/// Drops the outgoing secret once the new one works everywhere.
+pub fn confirm(path: &Path, alias: &str, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
 
-    if filtered_lines.len() == original_len {
-        return Err(format!("Error: No record for '{alias}' found"));
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    if before.active_previous(models::now()).is_none() {
+        println!("No pending rotation for '{alias}'.");
+        return Ok(());
     }
 
-    let data = filtered_lines.join("\n") + "\n";
-    file::overwrite_file(path, &data)
-        .map_err(|e| format!("Error: Failed to save changes: {e}"))?;
+    let mut record = Record { previous: None, ..before.clone() };
+    record.touch();
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(),
+        &format!("hermes: confirm rotation of {alias}"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Rotation of '{alias}' confirmed, the previous code is no longer listed.");
+    Ok(())
+}
+
+/// Lists the previous secrets of a record with their codes, newest first.
+pub fn history(path: &Path, alias: &str, password: &Option<String>) -> Result<(), String> {
+    let vault = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        open_existing(path)?
+    };
+    let seal_key = unlock_codex(vault.codex(), password)?;
+    let record = vault.get(alias).ok_or_else(|| format!("Alias '{alias}' not found."))?;
+
+    if record.history.is_empty() {
+        println!("No previous secrets for '{alias}'.");
+        return Ok(());
+    }
+
+    let needs_password = !record.is_unencrypted || record.history.iter().any(|v| !v.is_unencrypted);
+    let pass = match seal_key {
+        Some(key) => key,
+        None if needs_password => get_effective_password(password),
+        None => String::new(),
+    };
+
+    audit::record(path, "history", &[alias])?;
+
+    // version 0 is the current secret, rollback takes the others
+    let current = std::iter::once((0, record.clone(), "current".to_string()));
+    let previous = record.history.iter().rev().enumerate().map(|(i, v)| {
+        let version = Record {
+            secret: v.secret.clone(),
+            is_unencrypted: v.is_unencrypted,
+            ..record.clone()
+        };
+        (i + 1, version, ui::format_timestamp(v.replaced_at))
+    });
+
+    println!("{0: <7} | {1: <23} | {2: <10}", "Version", "Replaced", "OTP");
+    println!("{:-<7}-|-{:-<23}-|-{:-<10}", "", "", "");
+    for (n, version, replaced) in current.chain(previous) {
+        println!("{0: <7} | {1: <23} | {2: <10}", n, replaced, get_otp_display(&version, &pass));
+    }
+    Ok(())
+}
+
+/// Drops every previous secret of a record.
+pub fn history_purge(path: &Path, alias: &str, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    let count = before.history.len();
+    if count == 0 {
+        println!("No previous secrets for '{alias}'.");
+        return Ok(());
+    }
+
+    let mut record = Record { history: Vec::new(), ..before.clone() };
+    record.touch();
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(),
+        &format!("hermes: purge history of {alias}"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Purged {count} previous secret(s) of '{alias}'.");
+    Ok(())
+}
+
+/// Makes a previous secret current again, by default the last one replaced.
+pub fn rollback(path: &Path, alias: &str, to: Option<usize>, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    let version = to.unwrap_or(1);
+    let mut record = before.clone();
+    record.rollback(version)?;
+    record.touch();
+
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(),
+        &format!("hermes: rollback {alias} to version {version}"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Rolled back '{alias}' to version {version}, the replaced secret is now version 1.");
+    Ok(())
+}
+
+// locked records refuse every change but unlock
+fn ensure_unlocked(record: &Record) -> Result<(), Failure> {
+    match record.locked {
+        true => Err(Failure::locked(&record.alias)),
+        false => Ok(()),
+    }
+}
+
+/// Locks or unlocks one record, or the whole codex when alias is None.
+pub fn lock(path: &Path, alias: Option<&str>, locked: bool, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_existing(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+    let (verb, state) = if locked { ("lock", "locked") } else { ("unlock", "unlocked") };
+
+    let Some(alias) = alias else {
+        // a codex without header is version 0, which the default header says
+        let mut header = vault.codex().header.clone().unwrap_or_default();
+        if header.read_only == locked {
+            println!("The codex is already {state}.");
+            return Ok(());
+        }
+        header.read_only = locked;
+        vault.set_header(Some(header));
+        commit(&mut vault, path, seal_key.as_deref(), &format!("hermes: {verb} codex"), None)?;
+        println!("The codex is now {state}.");
+        return Ok(());
+    };
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    if before.locked == locked {
+        println!("Record '{alias}' is already {state}.");
+        return Ok(());
+    }
+    let mut record = Record { locked, ..before.clone() };
+    record.touch();
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(),
+        &format!("hermes: {verb} {alias}"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Record '{alias}' is now {state}.");
+    Ok(())
+}
+
+pub fn remove(path: &Path, alias: &str, permanent: bool, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let Some(record) = vault.get(alias).cloned() else {
+        return Err(format!("Error: No record for '{alias}' found").into());
+    };
+    ensure_unlocked(&record)?;
+
+    // trash first, a failed save then leaves a copy rather than nothing
+    if !permanent {
+        let mut items = trash::load(path)?;
+        items.push(Trashed::new(record.clone(), seal_key.as_deref()));
+        trash::save(path, &items)?;
+    }
+
+    vault.remove(alias);
+    commit_op(&mut vault, path, seal_key.as_deref(), Entry::new(Op::Remove, Some(record), None))?;
+
     println!("Record for {alias} removed.");
+    if !permanent {
+        println!("Moved to trash, restore it with `hermes trash restore {alias}`.");
+    }
+    Ok(())
+}
+
+fn open_existing(path: &Path) -> Result<Vault, String> {
+    if !file::file_exists(path) {
+        return Err("Codex not found.".into());
+    }
+    Vault::open(path)
+}
+
+// for commands that change the codex, refused early while it is locked
+fn open_writable(path: &Path) -> Result<Vault, Failure> {
+    let vault = open_existing(path)?;
+    ensure_writable(&vault)?;
+    Ok(vault)
+}
+
+// the password is only resolved here when it is needed for encryption
+fn resolve_password(is_unencrypt: &bool, password: &Option<String>) -> Option<String> {
+    if *is_unencrypt {
+        password.clone()
+    } else {
+        Some(get_effective_password(password))
+    }
+}
+
+// unparseable lines are kept on every rewrite, but the user should know
+fn warn_corrupt_lines(codex: &Codex) {
+    let corrupt = codex.corrupt_lines();
+    for (n, e) in &corrupt {
+        eprintln!("Warning: line {n} cannot be parsed: {e}");
+    }
+    if !corrupt.is_empty() {
+        eprintln!("Warning: run `hermes repair` to move unparseable lines to quarantine.");
+    }
+}
+
+/// Verifies the seal of a sealed codex and returns the key to re-seal it
+/// with. Unsealed codexes need no password.
+fn unlock_codex(codex: &Codex, password: &Option<String>) -> Result<Option<String>, String> {
+    warn_corrupt_lines(codex);
+
+    let Some(header) = codex.header.as_ref().filter(|h| h.is_sealed()) else {
+        return Ok(None);
+    };
+
+    let pass = get_effective_password(password);
+    if !integrity::verify_mac(&pass, header, &codex.lines) {
+        return Err(INTEGRITY_ERROR.into());
+    }
+    Ok(Some(pass))
+}
+
+/// Logs the change in the audit log, then saves the changes made to vault,
+/// with a backup first, and re-seals the result when a key is given. entry
+/// goes into the undo journal. With git sync enabled the change is committed
+/// with message.
+fn commit(
+    vault: &mut Vault,
+    path: &Path,
+    seal_key: Option<&str>,
+    message: &str,
+    entry: Option<Entry>,
+) -> Result<(), Failure> {
+    ensure_writable(vault)?;
+    let alias = entry.as_ref().and_then(|e| e.after.as_ref().or(e.before.as_ref())).map(|r| r.alias.clone());
+    record_in_audit(path, message, alias.as_deref())?;
+    save(vault, path, seal_key)?;
+
+    // save may have dropped an expired previous secret of the record
+    let entry = entry.map(|mut entry| {
+        if let Some(after) = entry.after.as_ref().and_then(|a| vault.get(&a.alias)) {
+            entry.after = Some(after.clone());
+        }
+        entry
+    });
+    record_in_journal(path, vault.codex(), entry, seal_key);
+    record_in_git(path, message);
     Ok(())
 }
 
+// a single record operation, the git message is derived from it
+fn commit_op(vault: &mut Vault, path: &Path, seal_key: Option<&str>, entry: Entry) -> Result<(), Failure> {
+    let message = format!("hermes: {entry}");
+    commit(vault, path, seal_key, &message, Some(entry))
+}
+
+// a codex locked with `hermes lock` takes no change but `hermes unlock`,
+// the one that clears the flag. Checked on the vault opened under the
+// exclusive lock, so no other process can lock the codex in between.
+fn ensure_writable(vault: &Vault) -> Result<(), Failure> {
+    let read_only = vault.codex().header.as_ref().is_some_and(|h| h.read_only);
+    match vault.was_read_only() && read_only {
+        true => Err(Failure::read_only("locked with `hermes lock`")),
+        false => Ok(()),
+    }
+}
+
+// the audited command is the first word of the change message, e.g. "add".
+// Like code displays, no change happens unless it is logged first.
+fn record_in_audit(path: &Path, message: &str, alias: Option<&str>) -> Result<(), String> {
+    let command = message.trim_start_matches("hermes: ").split(' ').next().unwrap_or_default();
+    audit::record(path, command, alias.as_slice())
+        .map_err(|e| format!("{e}, the codex was not changed."))
+}
+
+// the codex is already saved at this point, so git trouble is only a warning
+fn record_in_git(path: &Path, message: &str) {
+    if let Err(e) = git::record_change(path, message) {
+        eprintln!("Warning: git commit failed: {e}");
+    }
+}
+
+fn save(vault: &mut Vault, path: &Path, seal_key: Option<&str>) -> Result<(), String> {
+    migrations::ensure_supported(vault.codex())?;
+
+    // nothing worth a backup in a codex that was just created
+    if !vault.was_empty() {
+        file::create_backup(path)
+            .map_err(|e| format!("Warning: Backup failed: {}", e))?;
+    }
+
+    // grace periods end on the next write
+    vault.expire_previous(models::now());
+    reseal(vault, seal_key);
+    vault.save()
+}
+
+// like git, the journal trails the saved codex and only warns
+fn record_in_journal(path: &Path, codex: &Codex, entry: Option<Entry>, seal_key: Option<&str>) {
+    let result = Journal::load(path).and_then(|mut journal| {
+        // a tampered journal is dropped rather than MACed with our key
+        if journal.verify(seal_key).is_err() {
+            eprintln!("Warning: the journal failed its integrity check and was reset.");
+            journal = Journal::default();
+        }
+        journal.record(codex, entry);
+        journal.save(path, seal_key)
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: journal update failed: {e}");
+    }
+}
+
+fn reseal(vault: &mut Vault, seal_key: Option<&str>) {
+    let (Some(key), Some(mut header)) = (seal_key, vault.codex().header.clone()) else {
+        return;
+    };
+    header.mac = Some(integrity::compute_mac(key, &header, &vault.codex().lines));
+    vault.set_header(Some(header));
+}
+
 pub fn ls(
     path: &Path,
     alias_filter: &Option<String>,
-    is_unencrypt: &bool,
-    password: &Option<String>,
+    tags: &TagFilter,
+    encryption: &EncryptArgs,
     format: &OutputFormat,
+    columns: &[Column],
     quiet: bool,
 ) -> Result<(), String> {
-    let lines = file::read_file_to_vec(path)
-        .map_err(|_| "Codex not found.")?;
-    let records: Vec<Record> = lines.iter()
-        .filter_map(|l| Record::from_line(l)).collect();
+    if !file::file_exists(path) {
+        return Err("Codex not found.".into());
+    }
+    let EncryptArgs { unencrypt: is_unencrypt, password } = encryption;
+
+    let vault = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        open_existing(path)?
+    };
+    let seal_key = unlock_codex(vault.codex(), password)?;
 
     // apply search filter
-    let filtered: Vec<&Record> = records.iter()
+    let filtered: Vec<&Record> = vault.records()
         .filter(|r| match alias_filter {
             // partial match
-            Some(f) => r.alias.to_lowercase().contains(&f.to_lowercase()),
+            Some(f) => matches_filter(r, f),
             // display everything
             None => true,
         })
+        .filter(|r| matches_tags(r, tags))
         .collect();
 
     if filtered.is_empty() {
@@ -186,21 +676,26 @@ pub fn ls(
     }
 
     let needs_password = !*is_unencrypt && filtered.iter()
-        .any(|r| !r.is_unencrypted);
+        .any(|r| is_any_encrypted(r));
 
-    let pass = if needs_password {
-        get_effective_password(password)
-    } else {
-        String::new()
+    let pass = match seal_key {
+        Some(key) => key,
+        None if needs_password => get_effective_password(password),
+        None => String::new(),
     };
 
+    // no codes are shown unless the access is logged
+    let aliases: Vec<&str> = filtered.iter().map(|r| r.alias.as_str()).collect();
+    audit::record(path, "ls", &aliases)?;
+
     let rem = otp::get_remaining_seconds();
 
     match format {
-        OutputFormat::Json => print_json(&filtered, &pass, rem),
+        OutputFormat::Json => print_json(&filtered, &pass, rem, columns.contains(&Column::Notes)),
         OutputFormat::Table => print_table(&filtered,
             &pass,
             rem,
+            columns,
             alias_filter.is_some(),
             quiet),
     }
@@ -208,6 +703,99 @@ pub fn ls(
     Ok(())
 }
 
+/// Lists records of every vault, with the vault name as first column.
+/// All vaults are read with the same password.
+pub fn ls_all_vaults(
+    vaults: &BTreeMap<String, VaultEntry>,
+    alias_filter: &Option<String>,
+    tags: &TagFilter,
+    encryption: &EncryptArgs,
+    format: &OutputFormat,
+    columns: &[Column],
+) -> Result<(), String> {
+    let EncryptArgs { unencrypt: is_unencrypt, password } = encryption;
+    if vaults.is_empty() {
+        return Err("No vaults configured, add one with `hermes vault add <NAME> <PATH>`.".into());
+    }
+
+    let mut pass: Option<String> = None;
+    let mut found: Vec<(&str, Record)> = Vec::new();
+    for (name, vault) in vaults {
+        if !file::file_exists(&vault.path) {
+            eprintln!("Warning: vault '{name}' has no codex at {}", vault.path.display());
+            continue;
+        }
+
+        let opened = {
+            let _lock = file::lock_shared(&vault.path).map_err(|e| e.to_string())?;
+            Vault::open(&vault.path)?
+        };
+        let codex = opened.codex();
+        warn_corrupt_lines(codex);
+        if let Some(header) = codex.header.as_ref().filter(|h| h.is_sealed()) {
+            let key = pass.get_or_insert_with(|| get_effective_password(password));
+            if !integrity::verify_mac(key, header, &codex.lines) {
+                return Err(format!("Vault '{name}': {INTEGRITY_ERROR}"));
+            }
+        }
+
+        let matched: Vec<&Record> = opened.records()
+            .filter(|r| match alias_filter {
+                Some(f) => matches_filter(r, f),
+                None => true,
+            })
+            .filter(|r| matches_tags(r, tags))
+            .collect();
+        let aliases: Vec<&str> = matched.iter().map(|r| r.alias.as_str()).collect();
+        if !aliases.is_empty() {
+            audit::record(&vault.path, "ls", &aliases)?;
+        }
+        found.extend(matched.into_iter().cloned().map(|r| (name.as_str(), r)));
+    }
+
+    if found.is_empty() {
+        return Err("Alias not found.".into());
+    }
+
+    let needs_password = !*is_unencrypt && found.iter().any(|(_, r)| is_any_encrypted(r));
+    let pass = match pass {
+        Some(key) => key,
+        None if needs_password => get_effective_password(password),
+        None => String::new(),
+    };
+    let rem = otp::get_remaining_seconds();
+
+    match format {
+        OutputFormat::Json => {
+            let list: Vec<serde_json::Value> = found.iter().map(|(vault, r)| {
+                let mut value = serde_json::json!({
+                    "vault": vault,
+                    "alias": r.alias,
+                    "issuer": r.issuer,
+                    "account": r.account,
+                    "tags": r.tags,
+                    "otp": get_otp_display(r, &pass),
+                    "previous_otp": previous_otp(r, &pass),
+                    "remaining_secs": rem,
+                    "is_encrypted": !r.is_unencrypted,
+                    "created_at": r.created_at
+                });
+                if columns.contains(&Column::Notes) {
+                    value["notes"] = serde_json::json!(note_display(r, &pass));
+                }
+                value
+            }).collect();
+            println!("{}", serde_json::to_string_pretty(&list).unwrap());
+        }
+        OutputFormat::Table => {
+            let rows: Vec<(Option<&str>, &Record)> = found.iter().map(|(v, r)| (Some(*v), r)).collect();
+            print_rows(&rows, &pass, rem, columns);
+        }
+    }
+
+    Ok(())
+}
+
 fn get_otp_display(record: &Record, pass: &str) -> String {
     let secret = if record.is_unencrypted {
         Ok(record.secret.clone())
@@ -220,91 +808,1219 @@ fn get_otp_display(record: &Record, pass: &str) -> String {
         .unwrap_or_else(|_| "Error Invalid secret or decryption failed".to_string())
 }
 
+// case-insensitive partial match on alias, issuer or account
+fn matches_filter(record: &Record, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    [Some(&record.alias), record.issuer.as_ref(), record.account.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&filter))
+}
+
+// every --tag, or one of them with --any-tag; no --tag matches everything
+fn matches_tags(record: &Record, filter: &TagFilter) -> bool {
+    let has = |tag: &String| record.tags.contains(&tag.to_lowercase());
+    match filter.any_tag {
+        true => filter.tags.iter().any(has),
+        false => filter.tags.iter().all(has),
+    }
+}
+
+// the code of the outgoing secret while its grace period lasts
+fn previous_otp(record: &Record, pass: &str) -> Option<String> {
+    record.active_previous(models::now()).map(|p| {
+        let previous = Record {
+            secret: p.secret.clone(),
+            is_unencrypted: p.is_unencrypted,
+            ..record.clone()
+        };
+        get_otp_display(&previous, pass)
+    })
+}
+
+// true if showing record needs the password, for its secret or the outgoing one
+fn is_any_encrypted(record: &Record) -> bool {
+    !record.is_unencrypted
+        || record.active_previous(models::now()).is_some_and(|p| !p.is_unencrypted)
+        || record.notes.as_ref().is_some_and(|n| !n.is_unencrypted)
+}
+
+fn note_display(record: &Record, pass: &str) -> Option<String> {
+    let note = record.notes.as_ref()?;
+    match note.is_unencrypted {
+        true => Some(note.text.clone()),
+        false => Some(otp::decrypt(&note.text, pass)
+            .unwrap_or_else(|_| "Error decryption failed".to_string())),
+    }
+}
+
 fn print_table(
     records: &[&Record],
     pass: &str,
     rem: u64,
+    columns: &[Column],
     is_single_alias: bool,
     quiet: bool
 ) {
     if is_single_alias && records.len() == 1 {
         let code = get_otp_display(records[0], pass);
+        if let Some(previous) = previous_otp(records[0], pass).filter(|_| !quiet) {
+            eprintln!("Previous code: {previous}");
+        }
+        if columns.contains(&Column::Notes) && !quiet {
+            if let Some(note) = note_display(records[0], pass) {
+                eprintln!("Notes: {note}");
+            }
+        }
         ui::print_otp_with_progress(&code, rem, quiet);
         return;
     }
 
-    println!("{0: <15} | {1: <10} | {2: <4}", "Alias", "OTP", "Rem");
-    println!("{:-<15}-|-{:-<10}-|-{:-<4}", "", "", "");
-    for r in records {
-        let otp = get_otp_display(r, pass);
-        println!("{0: <15} | {1: <10} | {2:}s", r.alias, otp, rem);
+    let rows: Vec<(Option<&str>, &Record)> = records.iter().map(|r| (None, *r)).collect();
+    print_rows(&rows, pass, rem, columns);
+}
+
+fn column_title(column: Column) -> &'static str {
+    match column {
+        Column::Alias => "Alias",
+        Column::Issuer => "Issuer",
+        Column::Account => "Account",
+        Column::Tags => "Tags",
+        Column::Otp => "OTP",
+        Column::Previous => "Previous",
+        Column::Created => "Created",
+        Column::Rem => "Rem",
+        Column::Notes => "Notes",
+    }
+}
+
+fn column_width(column: Column) -> usize {
+    match column {
+        Column::Alias => 15,
+        Column::Issuer => 12,
+        Column::Account | Column::Tags => 20,
+        Column::Otp | Column::Previous => 10,
+        Column::Created => 23,
+        Column::Rem => 4,
+        Column::Notes => 30,
+    }
+}
+
+// rows with a vault name get a leading Vault column, the last column is
+// not padded
+fn print_rows(rows: &[(Option<&str>, &Record)], pass: &str, rem: u64, columns: &[Column]) {
+    // issuer and account show up once a record has them, right after alias
+    let mut columns = columns.to_vec();
+    let after_alias = columns.iter().position(|c| *c == Column::Alias).map_or(0, |i| i + 1);
+    let labels = [
+        (Column::Account, rows.iter().any(|(_, r)| r.account.is_some())),
+        (Column::Issuer, rows.iter().any(|(_, r)| r.issuer.is_some())),
+    ];
+    for (column, used) in labels {
+        if used && !columns.contains(&column) {
+            columns.insert(after_alias, column);
+        }
+    }
+
+    // the previous column shows up on its own during a rotation
+    let rotating = rows.iter().any(|(_, r)| previous_otp(r, pass).is_some());
+    if rotating && !columns.contains(&Column::Previous) {
+        let at = columns.iter().position(|c| *c == Column::Otp).map_or(columns.len(), |i| i + 1);
+        columns.insert(at, Column::Previous);
+    }
+    let with_vault = rows.iter().any(|(v, _)| v.is_some());
+
+    let mut header = Vec::new();
+    let mut rule = Vec::new();
+    if with_vault {
+        header.push(format!("{: <10}", "Vault"));
+        rule.push("-".repeat(10));
+    }
+    for &c in &columns {
+        header.push(format!("{: <w$}", column_title(c), w = column_width(c)));
+        rule.push("-".repeat(column_width(c)));
+    }
+    println!("{}", header.join(" | "));
+    println!("{}", rule.join("-|-"));
+
+    for (vault, r) in rows {
+        let mut cells = Vec::new();
+        if let Some(vault) = vault {
+            cells.push(format!("{vault: <10}"));
+        }
+        for (i, &c) in columns.iter().enumerate() {
+            let cell = match c {
+                Column::Alias => r.alias.clone(),
+                Column::Issuer => r.issuer.clone().unwrap_or_default(),
+                Column::Account => r.account.clone().unwrap_or_default(),
+                Column::Tags => r.tags.join(","),
+                Column::Otp => get_otp_display(r, pass),
+                Column::Previous => previous_otp(r, pass).unwrap_or_default(),
+                Column::Created => ui::format_timestamp(r.created_at),
+                Column::Rem => format!("{rem}s"),
+                // one line per row
+                Column::Notes => note_display(r, pass).unwrap_or_default().replace('\n', " / "),
+            };
+            match i + 1 == columns.len() {
+                true => cells.push(cell),
+                false => cells.push(format!("{cell: <w$}", w = column_width(c))),
+            }
+        }
+        println!("{}", cells.join(" | "));
     }
 }
 
-fn print_json(records: &[&Record], pass: &str, rem: u64) {
+fn print_json(records: &[&Record], pass: &str, rem: u64, show_notes: bool) {
     let list: Vec<serde_json::Value> = records.iter().map(|r| {
-        serde_json::json!({
+        let mut value = serde_json::json!({
             "alias": r.alias,
+            "issuer": r.issuer,
+            "account": r.account,
+            "tags": r.tags,
             "otp": get_otp_display(r, pass),
+            "previous_otp": previous_otp(r, pass),
             "remaining_secs": rem,
             "is_encrypted": !r.is_unencrypted,
             "created_at": r.created_at
-        })
+        });
+        if show_notes {
+            value["notes"] = serde_json::json!(note_display(r, pass));
+        }
+        value
     }).collect();
     println!("{}", serde_json::to_string_pretty(&list).unwrap());
 }
 
-pub fn migrate(path: &PathBuf) -> io::Result<()> {
-    // create backup
-    let backup_path = file::create_snapshot_backup(path)?;
-    println!("Backup created at {:?}", backup_path);
+pub fn migrate(path: &Path, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
 
-    // read and parse everything using the hybrid parser
-    let lines = file::read_file_to_vec(path)?;
-    let mut migrated_records = Vec::new();
-    let mut count = 0;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
 
-    for line in lines {
-        if let Some(record) = Record::from_line(&line) {
-            // re-serialize to JSON string
-            let json = serde_json::to_string(&record).expect("Failed to serialize");
-            migrated_records.push(json);
-            count += 1;
-        }
+    let (migrated, applied) = migrations::migrate(vault.codex().clone())?;
+    if applied.is_empty() {
+        println!("Codex is already at format version {CODEX_VERSION}, nothing to migrate.");
+        return Ok(());
+    }
+
+    for step in &applied {
+        println!("Applied migration {step}");
     }
 
-    // write back to the original file
-    let new_content = migrated_records.join("\n") + "\n";
-    file::overwrite_file(path, &new_content)?;
+    vault.replace(migrated);
+    commit(&mut vault, path, seal_key.as_deref(), "hermes: migrate codex", None)?;
+    let count = vault.records().count();
 
-    println!("Successfully migrated {count} records to JSON format.");
+    println!("Successfully migrated {count} records to format version {CODEX_VERSION}.");
     Ok(())
 }
 
-pub fn rename(path: &PathBuf, old_alias: &str, new_alias: &str) -> Result<(), String> {
-    // for Legacy file format
-    if new_alias.contains(':') {
-        return Err("The new alias cannot contain ':'".to_string());
+/// Rewrites the codex canonically and prints what changed.
+pub fn compact(path: &Path, order: SortBy, dry_run: bool, password: &Option<String>) -> Result<(), Failure> {
+    let lock = if dry_run { file::lock_shared(path) } else { file::lock_exclusive(path) };
+    let _lock = lock.map_err(|e| e.to_string())?;
+    let mut vault = match dry_run {
+        true => open_existing(path)?,
+        false => open_writable(path)?,
+    };
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let (compacted, report) = compact::compact(vault.codex(), order);
+    if report.corrupt_lines > 0 {
+        eprintln!("Warning: {} unparseable line(s) kept at the end, see `hermes repair`.", report.corrupt_lines);
+    }
+    let summary = report.summary();
+    if summary.is_empty() {
+        println!("Codex is already compact.");
+        return Ok(());
     }
 
-    if file::alias_exists(new_alias, path) {
-        return Err(format!("Alias '{new_alias}' already exists."));
+    for change in &summary {
+        println!("  {change}");
+    }
+    if dry_run {
+        println!("Dry run, nothing written.");
+        return Ok(());
     }
 
-    // read the file
-    let lines = file::read_file_to_vec(path).map_err(|e| e.to_string())?;
-    let mut target_record = lines.iter()
-        .filter_map(|l| Record::from_line(l))
-        .find(|r| r.alias == old_alias)
-        .ok_or_else(|| format!("Alias '{}' not found.", old_alias))?;
+    vault.replace(compacted);
+    commit(&mut vault, path, seal_key.as_deref(), "hermes: compact codex", None)?;
+    println!("Codex compacted.");
+    Ok(())
+}
 
-    target_record.alias = new_alias.to_string();
+pub fn rename(
+    path: &Path,
+    old_alias: &str,
+    new_alias: &str,
+    password: &Option<String>,
+) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
 
-    remove(path, old_alias)?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+    check_alias(vault.codex(), new_alias)?;
 
-    let json_data = serde_json::to_string(&target_record).map_err(|e| e.to_string())?;
-    file::append_to_file(path, &json_data).map_err(|e| e.to_string())?;
+    // in place, so created_at and the position in the codex survive
+    let before = vault.get(old_alias).cloned();
+    if let Some(record) = &before {
+        ensure_unlocked(record)?;
+    }
+    vault.rename(old_alias, new_alias)?;
+    let entry = Entry::new(Op::Rename, before, vault.get(new_alias).cloned());
+    commit_op(&mut vault, path, seal_key.as_deref(), entry)?;
 
     println!("Successfully renamed '{}' to '{}'", old_alias, new_alias);
     Ok(())
 }
+
+/// Adds tag to the record with alias, or removes it.
+pub fn tag(path: &Path, alias: &str, tag: &str, add: bool, password: &Option<String>) -> Result<(), Failure> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(format!("Invalid tag '{tag}': it must not be empty or contain spaces or commas.").into());
+    }
+
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    let mut record = before.clone();
+    let message = match add {
+        true if record.tags.contains(&tag) => {
+            println!("'{alias}' is already tagged '{tag}'.");
+            return Ok(());
+        }
+        true => {
+            record.tags.push(tag.clone());
+            format!("hermes: tag {alias} with {tag}")
+        }
+        false if !record.tags.contains(&tag) => {
+            return Err(format!("'{alias}' is not tagged '{tag}'.").into());
+        }
+        false => {
+            record.tags.retain(|t| *t != tag);
+            format!("hermes: untag {alias} from {tag}")
+        }
+    };
+    record.touch();
+
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(), &message,
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    match add {
+        true => println!("Tagged '{alias}' with '{tag}'."),
+        false => println!("Removed tag '{tag}' from '{alias}'."),
+    }
+    Ok(())
+}
+
+/// Lists every tag of the codex with the number of records carrying it.
+pub fn tags(path: &Path) -> Result<(), String> {
+    let vault = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        open_existing(path)?
+    };
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for tag in vault.records().flat_map(|r| &r.tags) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    if counts.is_empty() {
+        println!("No tags, add one with `hermes tag add <ALIAS> <TAG>`.");
+        return Ok(());
+    }
+
+    println!("{0: <20} | {1: <7}", "Tag", "Records");
+    println!("{:-<20}-|-{:-<7}", "", "");
+    for (tag, count) in counts {
+        println!("{0: <20} | {1: <7}", tag, count);
+    }
+    Ok(())
+}
+
+// priority 1 => TEXT argument
+// priority 2 => stdin, when it is piped, line breaks kept
+// priority 3 => interactive prompt for a single line
+fn get_effective_note(text: &Option<String>) -> Result<String, String> {
+    let input = match text {
+        Some(t) => t.clone(),
+        None if io::stdin().is_terminal() => {
+            eprint!("Enter note: ");
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).map_err(|e| format!("Failed to read note: {e}"))?;
+            line
+        }
+        None => {
+            let mut buf = String::new();
+            io::stdin().lock().read_to_string(&mut buf)
+                .map_err(|e| format!("Failed to read note from stdin: {e}"))?;
+            buf
+        }
+    };
+
+    let input = input.trim().to_string();
+    if input.is_empty() {
+        return Err("Error: No note provided, use `hermes note clear` to remove one.".into());
+    }
+    Ok(input)
+}
+
+// the password of the record's secret, checked against it so a mistyped
+// one cannot lock the note away
+fn record_password(record: &Record, seal_key: Option<String>, password: &Option<String>) -> Result<String, String> {
+    if record.is_unencrypted {
+        return Ok(String::new());
+    }
+    let pass = seal_key.unwrap_or_else(|| get_effective_password(password));
+    otp::decrypt(&record.secret, &pass)
+        .map_err(|_| format!("Wrong password for '{}'.", record.alias))?;
+    Ok(pass)
+}
+
+/// Replaces the note of the record with alias, encrypted like its secret.
+pub fn note_set(path: &Path, alias: &str, text: &Option<String>, password: &Option<String>) -> Result<(), Failure> {
+    let text = get_effective_note(text)?;
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    let pass = record_password(&before, seal_key.clone(), password)?;
+    let note = match before.is_unencrypted {
+        true => Note { text, is_unencrypted: true },
+        false => Note { text: otp::encrypt(&text, &pass), is_unencrypted: false },
+    };
+
+    let mut record = Record { notes: Some(note), ..before.clone() };
+    record.touch();
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(), &format!("hermes: note {alias}"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Note of '{alias}' saved.");
+    Ok(())
+}
+
+pub fn note_show(path: &Path, alias: &str, password: &Option<String>) -> Result<(), String> {
+    let vault = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        open_existing(path)?
+    };
+    let seal_key = unlock_codex(vault.codex(), password)?;
+    let record = vault.get(alias).ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    let Some(note) = &record.notes else {
+        println!("No note for '{alias}'.");
+        return Ok(());
+    };
+
+    let pass = match note.is_unencrypted {
+        true => String::new(),
+        false => seal_key.unwrap_or_else(|| get_effective_password(password)),
+    };
+    let text = match note.is_unencrypted {
+        true => note.text.clone(),
+        false => otp::decrypt(&note.text, &pass)
+            .map_err(|_| format!("Wrong password for the note of '{alias}'."))?,
+    };
+
+    // no notes are shown unless the access is logged
+    audit::record(path, "note", &[alias])?;
+    println!("{text}");
+    Ok(())
+}
+
+pub fn note_clear(path: &Path, alias: &str, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let before = vault.get(alias).cloned().ok_or_else(|| format!("Alias '{alias}' not found."))?;
+    ensure_unlocked(&before)?;
+    if before.notes.is_none() {
+        println!("No note for '{alias}'.");
+        return Ok(());
+    }
+
+    let mut record = Record { notes: None, ..before.clone() };
+    record.touch();
+    vault.update(alias, record.clone());
+    commit(&mut vault, path, seal_key.as_deref(), &format!("hermes: note of {alias} cleared"),
+        Some(Entry::new(Op::Update, Some(before), Some(record))))?;
+
+    println!("Note of '{alias}' cleared.");
+    Ok(())
+}
+
+/// Seals the codex with a MAC keyed by the password. Also used to re-seal
+/// after a deliberate manual edit, so the current MAC is not checked.
+pub fn seal(path: &Path, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    if !file::file_exists(path) {
+        return Err("Codex not found.".into());
+    }
+    let mut vault = Vault::open_unchecked(path)?;
+    ensure_writable(&vault)?;
+    let pass = get_effective_password(password);
+
+    // a typo here would lock the user out of every later command
+    let undecryptable: Vec<&str> = vault.records()
+        .filter(|r| !r.is_unencrypted && otp::decrypt(&r.secret, &pass).is_err())
+        .map(|r| r.alias.as_str())
+        .collect();
+    if !undecryptable.is_empty() {
+        return Err(format!("Password does not decrypt: {}", undecryptable.join(", ")).into());
+    }
+
+    let header = vault.codex().header.clone().unwrap_or_else(|| {
+        // a header on an older codex must not claim the current format
+        let version = migrations::detect_version(vault.codex());
+        Header { version, ..Header::default() }
+    });
+    vault.set_header(Some(header));
+
+    // journal and trash are taken as they are, like the codex itself
+    let mut journal = Journal::load(path)?;
+    journal.save(path, Some(&pass))?;
+    let mut items = trash::load(path)?;
+    if !items.is_empty() {
+        items.iter_mut().for_each(|t| t.seal(&pass));
+        trash::save(path, &items)?;
+    }
+    commit(&mut vault, path, Some(&pass), "hermes: seal codex", None)?;
+    integrity::mark_sealed(path)?;
+    println!("Codex sealed.");
+    Ok(())
+}
+
+pub fn backup_list(path: &Path) -> Result<(), String> {
+    let backups = file::list_backups(path).map_err(|e| e.to_string())?;
+    if backups.is_empty() {
+        println!("No backups found in {}", file::backups_dir(path).display());
+        return Ok(());
+    }
+
+    println!("{0: <17} | {1: <23} | {2: <7}", "ID", "Created", "Records");
+    println!("{:-<17}-|-{:-<23}-|-{:-<7}", "", "", "");
+    for b in backups.iter().rev() {
+        let records = Vault::open(&b.path)
+            .map(|v| v.records().count().to_string())
+            .unwrap_or_else(|_| "?".to_string());
+        println!("{0: <17} | {1: <23} | {2: <7}",
+            b.id, ui::format_timestamp(b.created_at), records);
+    }
+    Ok(())
+}
+
+pub fn backup_restore(path: &Path, id: &str) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    if file::file_exists(path) {
+        ensure_writable(&Vault::open_unchecked(path)?)?;
+    }
+    let backup = file::find_backup(path, id).map_err(|e| e.to_string())?;
+    let data = std::fs::read(&backup.path)
+        .map_err(|e| format!("Error: Failed to read backup: {e}"))?;
+
+    // keep the current state as a generation too, so a restore can be undone
+    if file::file_exists(path) {
+        let current = file::create_backup(path)
+            .map_err(|e| format!("Warning: Backup failed: {}", e))?;
+        println!("Current codex saved to {}", current.display());
+    }
+
+    let message = format!("hermes: restore backup {id}");
+    record_in_audit(path, &message, None)?;
+    file::overwrite_file(path, &data)
+        .map_err(|e| format!("Error: Failed to restore backup: {e}"))?;
+    record_in_git(path, &message);
+    println!("Codex restored from backup {id}.");
+    let restored = Vault::open_unchecked(path)?;
+    if integrity::check_seal_present(path, restored.codex()).is_err() {
+        eprintln!("Warning: the backup is from before the codex was sealed, run `hermes seal` to use it.");
+    }
+    Ok(())
+}
+
+pub fn backup_prune(path: &Path, keep: Option<usize>) -> Result<(), String> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let keep = keep.unwrap_or_else(file::backup_retention);
+    let removed = file::prune_backups(path, keep).map_err(|e| e.to_string())?;
+    println!("Removed {} backup(s), keeping the newest {keep}.", removed.len());
+    Ok(())
+}
+
+/// Prints one setting, or the whole config file without key.
+pub fn config_get(key: Option<&str>) -> Result<(), String> {
+    let config = Config::load()?;
+    let Some(key) = key else {
+        print!("{}", toml::to_string(&config).map_err(|e| e.to_string())?);
+        return Ok(());
+    };
+    match config.get(key)? {
+        Some(value) => println!("{value}"),
+        None => return Err(format!("'{key}' is not set.")),
+    }
+    Ok(())
+}
+
+pub fn config_set(key: &str, value: &str) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.set(key, value)?;
+    config.save()?;
+    println!("Set {key} in {}.", config::config_path().display());
+    Ok(())
+}
+
+pub fn config_unset(key: &str) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.unset(key)?;
+    config.save()?;
+    println!("Unset {key} in {}.", config::config_path().display());
+    Ok(())
+}
+
+/// Opens the config file in $VISUAL or $EDITOR on a copy, which only
+/// replaces the config once it parses.
+pub fn config_edit() -> Result<(), String> {
+    let path = config::config_path();
+    let current = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read config {}: {e}", path.display())),
+    };
+
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".edit");
+    let draft = std::path::PathBuf::from(name);
+    file::ensure_dir_exists(&path)
+        .and_then(|_| file::overwrite_file(&draft, current.as_bytes()))
+        .map_err(|e| format!("Failed to prepare config for editing: {e}"))?;
+
+    let result = edit_draft(&draft);
+    let _ = std::fs::remove_file(&draft);
+    let content = result?;
+    if content == current {
+        println!("Config unchanged.");
+        return Ok(());
+    }
+
+    file::overwrite_file(&path, content.as_bytes())
+        .map_err(|e| format!("Failed to write config {}: {e}", path.display()))?;
+    println!("Config saved to {}.", path.display());
+    Ok(())
+}
+
+// runs the editor on draft and returns the edited content once it parses
+fn edit_draft(draft: &Path) -> Result<String, String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+
+    // editors like "code --wait" come with arguments
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(draft)
+        .status()
+        .map_err(|e| format!("Failed to run editor '{editor}': {e}"))?;
+    if !status.success() {
+        return Err(format!("Editor '{editor}' failed, config unchanged."));
+    }
+
+    let content = std::fs::read_to_string(draft).map_err(|e| e.to_string())?;
+    toml::from_str::<Config>(&content)
+        .map_err(|e| format!("Invalid config, nothing changed: {e}"))?;
+    Ok(content)
+}
+
+pub fn vault_list() -> Result<(), String> {
+    let config = Config::load()?;
+    if config.vaults.is_empty() {
+        println!("No vaults configured, add one with `hermes vault add <NAME> <PATH>`.");
+        return Ok(());
+    }
+
+    println!("{0: <20} | Path", "Vault");
+    println!("{:-<20}-|-{:-<4}", "", "");
+    for (name, vault) in &config.vaults {
+        let label = match config.default_vault.as_deref() == Some(name.as_str()) {
+            true => format!("{name} (default)"),
+            false => name.clone(),
+        };
+        println!("{0: <20} | {1}", label, vault.path.display());
+    }
+    Ok(())
+}
+
+pub fn vault_add(name: &str, path: &Path) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.add_vault(name, path)?;
+    config.save()?;
+    println!("Vault '{name}' added.");
+    Ok(())
+}
+
+pub fn vault_remove(name: &str) -> Result<(), String> {
+    let mut config = Config::load()?;
+    let vault = config.remove_vault(name)?;
+    config.save()?;
+    println!("Vault '{name}' removed, its codex stays at {}", vault.path.display());
+    Ok(())
+}
+
+pub fn vault_default(name: &str) -> Result<(), String> {
+    let mut config = Config::load()?;
+    config.vault_path(name)?;
+    config.default_vault = Some(name.to_string());
+    config.save()?;
+    println!("Vault '{name}' is now the default.");
+    Ok(())
+}
+
+pub fn trash_list(path: &Path) -> Result<(), String> {
+    let items = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        trash::load(path)?
+    };
+    if items.is_empty() {
+        println!("Trash is empty.");
+        return Ok(());
+    }
+
+    println!("{0: <15} | {1: <23}", "Alias", "Deleted");
+    println!("{:-<15}-|-{:-<23}", "", "");
+    for item in items.iter().rev() {
+        println!("{0: <15} | {1: <23}", item.record.alias, ui::format_timestamp(item.deleted_at));
+    }
+    Ok(())
+}
+
+/// Moves the most recently removed record with alias back into the codex,
+/// under a new alias if the old one is taken by now.
+pub fn trash_restore(path: &Path, alias: &str, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let mut items = trash::load(path)?;
+    let index = items.iter()
+        .rposition(|t| t.record.alias == alias)
+        .ok_or_else(|| format!("No record for '{alias}' in trash."))?;
+    if !items[index].verify(seal_key.as_deref()) {
+        return Err(format!("Integrity check failed: the trashed record for '{alias}' was \
+            modified outside hermes, refusing to restore it.").into());
+    }
+
+    let mut record = items[index].record.clone();
+    record.alias = trash::free_alias(alias, |a| vault.contains(a));
+    let restored_as = record.alias.clone();
+
+    vault.insert(record.clone())?;
+    commit(&mut vault, path, seal_key.as_deref(),
+        &format!("hermes: restore {restored_as} from trash"),
+        Some(Entry::new(Op::Add, None, Some(record))))?;
+
+    // the codex is saved, a stale trash entry is only a duplicate
+    items.remove(index);
+    if let Err(e) = trash::save(path, &items) {
+        eprintln!("Warning: {e}");
+    }
+
+    match restored_as == alias {
+        true => println!("Record for {alias} restored."),
+        false => println!("Record for {alias} restored as '{restored_as}', '{alias}' already exists."),
+    }
+    Ok(())
+}
+
+/// Deletes trashed records for good, all of them or those removed more than
+/// older_than days ago.
+pub fn trash_purge(path: &Path, older_than: Option<u64>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    if file::file_exists(path) {
+        ensure_writable(&Vault::open_unchecked(path)?)?;
+    }
+    let items = trash::load(path)?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let cutoff = older_than.map_or(u64::MAX, |days| now.saturating_sub(days.saturating_mul(86_400)));
+
+    let (purged, kept): (Vec<Trashed>, Vec<Trashed>) = items
+        .into_iter()
+        .partition(|t| t.deleted_at <= cutoff);
+    if !purged.is_empty() {
+        let aliases: Vec<&str> = purged.iter().map(|t| t.record.alias.as_str()).collect();
+        audit::record(path, "purge", &aliases)?;
+        trash::save(path, &kept)?;
+    }
+
+    println!("Purged {} record(s) from trash, {} left.", purged.len(), kept.len());
+    Ok(())
+}
+
+/// Reverts the last add, remove, update or rename.
+pub fn undo(path: &Path, password: &Option<String>) -> Result<(), Failure> {
+    replay(path, password, true)
+}
+
+/// Applies the last undone operation again.
+pub fn redo(path: &Path, password: &Option<String>) -> Result<(), Failure> {
+    replay(path, password, false)
+}
+
+fn replay(path: &Path, password: &Option<String>, undo: bool) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let mut journal = Journal::load(path)?;
+    journal.verify(seal_key.as_deref())?;
+    let (verb, entry) = match undo {
+        true => ("Undid", journal.undo(&mut vault)?),
+        false => ("Redid", journal.redo(&mut vault)?),
+    };
+    let Some(entry) = entry else {
+        println!("Nothing to {}.", if undo { "undo" } else { "redo" });
+        return Ok(());
+    };
+
+    // a redone remove trashes the record again, like `remove` does
+    if let (false, Op::Remove, Some(record)) = (undo, entry.op, &entry.before) {
+        let mut items = trash::load(path)?;
+        items.push(Trashed::new(record.clone(), seal_key.as_deref()));
+        trash::save(path, &items)?;
+    }
+
+    let message = format!("hermes: {} {entry}", verb.to_lowercase());
+    let alias = entry.after.as_ref().or(entry.before.as_ref()).map(|r| r.alias.as_str());
+    record_in_audit(path, &message, alias)?;
+    save(&mut vault, path, seal_key.as_deref())?;
+    journal.record(vault.codex(), None);
+    journal.save(path, seal_key.as_deref())?;
+    record_in_git(path, &message);
+
+    // the record is back, so its copy must not be restored a second time
+    if let (true, Op::Remove, Some(record)) = (undo, entry.op, &entry.before) {
+        if let Err(e) = trash::take(path, record) {
+            eprintln!("Warning: trash update failed: {e}");
+        }
+    }
+
+    println!("{verb}: {entry}");
+    Ok(())
+}
+
+/// Moves unparseable lines out of the codex into `<codex>.quarantine`.
+pub fn repair(path: &Path, yes: bool, password: &Option<String>) -> Result<(), Failure> {
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let seal_key = unlock_codex(vault.codex(), password)?;
+
+    let corrupt = vault.codex().corrupt_lines();
+    if corrupt.is_empty() {
+        println!("No unparseable lines found.");
+        return Ok(());
+    }
+
+    let question = format!("Move {} line(s) to quarantine?", corrupt.len());
+    if !yes && !ui::confirm(&question) {
+        return Err("Aborted, codex left unchanged.".into());
+    }
+
+    let mut codex = vault.codex().clone();
+    let bad = codex.take_corrupt_lines();
+    let quarantine = file::quarantine_lines(path, &bad).map_err(|e| e.to_string())?;
+    vault.replace(codex);
+    commit(&mut vault, path, seal_key.as_deref(), "hermes: quarantine unparseable lines", None)?;
+
+    println!("Moved {} line(s) to {}", bad.len(), quarantine.display());
+    Ok(())
+}
+
+/// Copies the codex into a new file of the backend matching dest.
+pub fn convert(path: &Path, dest: &Path) -> Result<(), String> {
+    if file::file_exists(dest) {
+        return Err(format!("Error: {} already exists.", dest.display()));
+    }
+
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let source = open_existing(path)?;
+    warn_corrupt_lines(source.codex());
+
+    // header and lines are copied verbatim, so a seal stays valid
+    file::ensure_dir_exists(dest).map_err(|e| e.to_string())?;
+    let mut target = Vault::open(dest)?;
+    target.replace(source.codex().clone());
+    target.save()?;
+    if integrity::is_marked_sealed(path) {
+        integrity::mark_sealed(dest)?;
+    }
+    let count = target.records().count();
+
+    println!(
+        "Converted {count} records from {} to {} at {}",
+        source.kind(),
+        target.kind(),
+        dest.display()
+    );
+    Ok(())
+}
+
+/// Shows which records another codex adds, lacks or holds differently.
+/// Secrets and notes are compared decrypted, so the codexes may use
+/// different passwords and formats.
+pub fn diff(
+    path: &Path,
+    other: &Path,
+    password: &Option<String>,
+    other_password: &Option<String>,
+) -> Result<(), String> {
+    let (ours, theirs) = {
+        let _lock = file::lock_shared(path).map_err(|e| e.to_string())?;
+        let _other_lock = file::lock_shared(other).map_err(|e| e.to_string())?;
+        (open_existing(path)?, open_other(other)?)
+    };
+
+    let (our_pass, ours) = decrypt_codex(path, &ours, || get_effective_password(password))?;
+    let (_, theirs) = decrypt_codex(other, &theirs, || {
+        other_password_or(other, other_password, our_pass.as_deref())
+    })?;
+
+    let changes = merge::diff(&ours, &theirs, same_record);
+    if changes.is_empty() {
+        println!("No differences.");
+        return Ok(());
+    }
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in &changes {
+        match change {
+            Change::Added(r) => {
+                added += 1;
+                println!("+ {}", r.alias);
+            }
+            Change::Removed(r) => {
+                removed += 1;
+                println!("- {}", r.alias);
+            }
+            Change::Changed { ours, theirs } => {
+                changed += 1;
+                println!("~ {} (ours {}, theirs {})", ours.alias,
+                    ui::format_timestamp(ours.changed_at()),
+                    ui::format_timestamp(theirs.changed_at()));
+            }
+        }
+    }
+    println!("{added} added, {removed} removed, {changed} changed.");
+    Ok(())
+}
+
+/// Adds the records of another codex and resolves records that differ
+/// with strategy. Records only this codex has are kept.
+pub fn merge(
+    path: &Path,
+    other: &Path,
+    password: &Option<String>,
+    other_password: &Option<String>,
+    strategy: ConflictStrategy,
+) -> Result<(), Failure> {
+    // the shared lock on other would wait for our own exclusive lock
+    if path.canonicalize().ok().is_some_and(|p| other.canonicalize().ok() == Some(p)) {
+        return Err("Cannot merge a codex with itself.".into());
+    }
+
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let theirs = {
+        let _other_lock = file::lock_shared(other).map_err(|e| e.to_string())?;
+        open_other(other)?
+    };
+
+    let (mut our_pass, ours) = decrypt_codex(path, &vault, || get_effective_password(password))?;
+    let (_, theirs) = decrypt_codex(other, &theirs, || {
+        other_password_or(other, other_password, our_pass.as_deref())
+    })?;
+
+    let (mut added, mut replaced, mut kept) = (0, 0, 0);
+    let mut incoming = Vec::new();
+    for change in merge::diff(&ours, &theirs, same_record) {
+        match change {
+            Change::Added(t) => {
+                added += 1;
+                incoming.push(t);
+            }
+            Change::Removed(_) => {}
+            Change::Changed { ours, theirs } => {
+                let take_theirs = match strategy {
+                    ConflictStrategy::Newest => merge::newer(*ours, (*theirs).clone()) == *theirs,
+                    ConflictStrategy::Ours => false,
+                    ConflictStrategy::Theirs => true,
+                    ConflictStrategy::Interactive => ui::keep_theirs(&format!(
+                        "'{}' differs (ours {}, theirs {}). Keep",
+                        theirs.alias,
+                        ui::format_timestamp(ours.changed_at()),
+                        ui::format_timestamp(theirs.changed_at())
+                    )),
+                };
+                if take_theirs {
+                    replaced += 1;
+                    incoming.push(*theirs);
+                } else {
+                    kept += 1;
+                }
+            }
+        }
+    }
+
+    if incoming.is_empty() {
+        println!("Nothing to merge, {kept} conflict(s) kept as ours.");
+        return Ok(());
+    }
+    if let Some(locked) = incoming.iter().filter_map(|r| vault.get(&r.alias)).find(|o| o.locked) {
+        return Err(Failure::locked(&locked.alias));
+    }
+
+    // incoming secrets and notes are plain text here, encrypt them with our password
+    let seal_key = vault.codex().header.as_ref()
+        .filter(|h| h.is_sealed())
+        .and(our_pass.clone());
+    for mut r in incoming {
+        r.map_encrypted(|text| {
+            let key = our_pass.get_or_insert_with(|| get_effective_password(password));
+            Ok::<_, String>(otp::encrypt(text, key))
+        })?;
+        if vault.contains(&r.alias) {
+            vault.update(&r.alias.clone(), r);
+        } else {
+            vault.insert(r)?;
+        }
+    }
+    commit(&mut vault, path, seal_key.as_deref(), &format!("hermes: merge {}", other.display()), None)?;
+
+    println!("Merged {}: {added} added, {replaced} replaced, {kept} kept.", other.display());
+    Ok(())
+}
+
+fn open_other(other: &Path) -> Result<Vault, String> {
+    if !file::file_exists(other) {
+        return Err(format!("Codex not found: {}", other.display()));
+    }
+    Vault::open(other)
+}
+
+// the other codex usually shares our password, so only prompt without one
+fn other_password_or(other: &Path, other_password: &Option<String>, ours: Option<&str>) -> String {
+    other_password
+        .clone()
+        .or_else(|| ours.map(str::to_string))
+        .unwrap_or_else(|| rpassword::prompt_password(format!("Enter password for {}: ", other.display()))
+            .expect("Failed to read password"))
+}
+
+/// Verifies the seal and decrypts every encrypted field of vault. The
+/// records keep their is_unencrypted flags, so merged ones can be encrypted
+/// again. The password is only asked for when the codex needs one.
+fn decrypt_codex(
+    path: &Path,
+    vault: &Vault,
+    mut password: impl FnMut() -> String,
+) -> Result<(Option<String>, Vec<Record>), String> {
+    let codex = vault.codex();
+    warn_corrupt_lines(codex);
+    let records: Vec<Record> = vault.records().cloned().collect();
+    let sealed = codex.header.as_ref().filter(|h| h.is_sealed());
+    let mut pass = sealed.is_some().then(&mut password);
+
+    if let (Some(header), Some(pass)) = (sealed, &pass) {
+        if !integrity::verify_mac(pass, header, &codex.lines) {
+            return Err(format!("{}: {INTEGRITY_ERROR}", path.display()));
+        }
+    }
+
+    let plain = records
+        .into_iter()
+        .map(|mut r| {
+            let alias = r.alias.clone();
+            r.map_encrypted(|text| {
+                otp::decrypt(text, pass.get_or_insert_with(&mut password))
+                    .map_err(|_| format!(
+                        "Cannot decrypt '{alias}' in {}, wrong password? Use --other-password for the other codex.",
+                        path.display()
+                    ))
+            })?;
+            Ok(r)
+        })
+        .collect::<Result<Vec<Record>, String>>()?;
+    Ok((pass, plain))
+}
+
+// records match when every field but their timestamps does, secrets and
+// notes compared decrypted, wherever and however they are stored
+fn same_record(a: &Record, b: &Record) -> bool {
+    let content = |r: &Record| {
+        let mut r = r.clone();
+        (r.created_at, r.updated_at) = (0, None);
+        r.is_unencrypted = true;
+        r.history.iter_mut().for_each(|v| v.is_unencrypted = true);
+        if let Some(previous) = r.previous.as_mut() {
+            previous.is_unencrypted = true;
+        }
+        if let Some(note) = r.notes.as_mut() {
+            note.is_unencrypted = true;
+        }
+        r
+    };
+    content(a) == content(b)
+}
+
+pub fn audit_enable(path: &Path) -> Result<(), String> {
+    if !file::file_exists(path) {
+        return Err("Codex not found.".into());
+    }
+    match audit::enable(path)? {
+        true => println!("Auditing enabled, entries go to {}.", audit::audit_path(path).display()),
+        false => println!("Auditing is already enabled."),
+    }
+    Ok(())
+}
+
+pub fn audit_verify(path: &Path) -> Result<(), String> {
+    let count = audit::verify(path)?;
+    println!("Audit log intact, {count} entries.");
+    Ok(())
+}
+
+pub fn audit_show(path: &Path, alias: Option<&str>) -> Result<(), String> {
+    if !audit::is_enabled(path) {
+        return Err("Auditing is not enabled, see `hermes audit enable`.".into());
+    }
+    let entries: Vec<audit::AuditEntry> = audit::load(path)?
+        .into_iter()
+        .filter(|e| alias.is_none_or(|a| e.event.alias.as_deref() == Some(a)))
+        .collect();
+    if entries.is_empty() {
+        println!("No audit entries.");
+        return Ok(());
+    }
+
+    println!("{0: <5} | {1: <23} | {2: <25} | {3: <10} | Alias", "Seq", "Time", "User", "Command");
+    println!("{:-<5}-|-{:-<23}-|-{:-<25}-|-{:-<10}-|-{:-<15}", "", "", "", "", "");
+    for entry in entries {
+        let e = entry.event;
+        println!("{0: <5} | {1: <23} | {2: <25} | {3: <10} | {4}",
+            e.seq,
+            ui::format_timestamp(e.at),
+            format!("{}@{}", e.user, e.host),
+            e.command,
+            e.alias.unwrap_or_default());
+    }
+    Ok(())
+}
+
+pub fn git_init(path: &Path, remote: Option<&str>) -> Result<(), String> {
+    file::ensure_dir_exists(path).map_err(|e| e.to_string())?;
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    git::init(path, remote)?;
+    println!("Git sync enabled, every change is committed in the codex directory.");
+    Ok(())
+}
+
+/// Pulls the remote codex, merges it record by record and pushes the result.
+pub fn sync(path: &Path, password: &Option<String>) -> Result<(), Failure> {
+    if !git::is_enabled(path) {
+        return Err("Git sync is not enabled, run `hermes git init --remote <URL>` first.".into());
+    }
+    if !git::has_remote(path) {
+        return Err("No git remote configured, run `hermes git init --remote <URL>`.".into());
+    }
+
+    let _lock = file::lock_exclusive(path).map_err(|e| e.to_string())?;
+    let mut vault = open_writable(path)?;
+    let mut seal_key = unlock_codex(vault.codex(), password)?;
+
+    git::record_change(path, "hermes: save local changes")?;
+    let branch = git::current_branch(path)?;
+    git::fetch(path)?;
+    let upstream = git::remote_ref(&branch);
+
+    if !git::ref_exists(path, &upstream) || git::is_ancestor(path, &upstream, "HEAD") {
+        git::push(path, &branch)?;
+        println!("Pushed local changes to {upstream}.");
+        return Ok(());
+    }
+
+    // never take in a remote codex that fails its own seal
+    let theirs = load_revision(path, &upstream)?.unwrap_or_default();
+    migrations::ensure_supported(&theirs)?;
+    if let Some(header) = theirs.header.as_ref().filter(|h| h.is_sealed()) {
+        let key = seal_key.get_or_insert_with(|| get_effective_password(password));
+        if !integrity::verify_mac(key, header, &theirs.lines) {
+            return Err(format!("Remote codex at {upstream} failed the integrity check.").into());
+        }
+    }
+
+    // locked records only change after `hermes unlock`
+    let our_records = vault.codex().records();
+
+    if git::is_ancestor(path, "HEAD", &upstream) {
+        if let Some(locked) = merge::changed_locked(&our_records, &theirs.records()) {
+            return Err(Failure::locked(&locked.alias));
+        }
+        record_in_audit(path, "hermes: sync", None)?;
+        git::fast_forward(path, &upstream)?;
+        // git checks files out with the umask, not our mode
+        file::make_private(path).map_err(|e| e.to_string())?;
+        println!("Fast-forwarded to {upstream}.");
+        return Ok(());
+    }
+
+    let base = git::merge_base(path, "HEAD", &upstream)
+        .map(|rev| load_revision(path, &rev))
+        .transpose()?
+        .flatten()
+        .unwrap_or_default();
+    let merged = merge::three_way(&base.records(), &our_records, &theirs.records());
+    if let Some(locked) = merge::changed_locked(&our_records, &merged) {
+        return Err(Failure::locked(&locked.alias));
+    }
+    let ours = vault.codex().clone();
+
+    // our unparseable lines stay, hermes never drops them silently
+    let mut lines: Vec<String> = merged.iter().map(Record::to_string).collect();
+    lines.extend(ours.lines.iter().filter(|l| Record::parse_line(l).is_err()).cloned());
+    vault.replace(Codex {
+        header: ours.header.or(theirs.header),
+        lines,
+    });
+
+    record_in_audit(path, "hermes: sync", None)?;
+    save(&mut vault, path, seal_key.as_deref())?;
+    record_in_journal(path, vault.codex(), None, seal_key.as_deref());
+    git::record_merge(path, &upstream, &format!("hermes: sync merge with {upstream}"))?;
+    git::push(path, &branch)?;
+
+    println!("Merged {} records with {upstream} and pushed.", merged.len());
+    Ok(())
+}
+
+// loads the codex as committed at rev through a temp file next to it
+fn load_revision(path: &Path, rev: &str) -> Result<Option<Codex>, String> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{name}.tmp.sync.{}", std::process::id()));
+
+    let result = match git::export_codex(path, rev, &tmp) {
+        Ok(true) => storage::open(&tmp).and_then(|s| s.load()).map(Some),
+        Ok(false) => Ok(None),
+        Err(e) => Err(e),
+    };
+    let _ = std::fs::remove_file(&tmp);
+    result
+}
@@ -0,0 +1,109 @@
+use crate::args::SortBy;
+use crate::models::{Codex, Record};
+
+/// What `compact` changed, for its summary.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub blank_lines: usize,
+    pub legacy_lines: usize,
+    pub reformatted_lines: usize,
+    pub normalized_secrets: usize,
+    pub duplicates: usize,
+    pub reordered: bool,
+    /// Unparseable lines, kept verbatim at the end
+    pub corrupt_lines: usize,
+}
+
+impl Report {
+    /// One line per kind of change, empty if the codex was already compact.
+    pub fn summary(&self) -> Vec<String> {
+        let counts = [
+            (self.blank_lines, "blank line(s) removed"),
+            (self.legacy_lines, "legacy line(s) converted to JSON"),
+            (self.reformatted_lines, "JSON line(s) reformatted"),
+            (self.normalized_secrets, "secret(s) normalized"),
+            (self.duplicates, "duplicate record(s) merged"),
+        ];
+        let mut summary: Vec<String> = counts
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, what)| format!("{n} {what}"))
+            .collect();
+        if self.reordered {
+            summary.push("records sorted".into());
+        }
+        summary
+    }
+}
+
+/// Rewrites the record lines canonically: one JSON record per line in a
+/// stable order, unencrypted secrets in canonical Base32 and exact
+/// duplicates merged. Unparseable lines are moved to the end, never dropped.
+pub fn compact(codex: &Codex, order: SortBy) -> (Codex, Report) {
+    let mut report = Report::default();
+    let mut records: Vec<Record> = Vec::new();
+    let mut corrupt: Vec<String> = Vec::new();
+
+    for line in &codex.lines {
+        match Record::parse_line(line) {
+            Ok(Some(record)) => {
+                let trimmed = line.trim();
+                if !trimmed.starts_with('{') {
+                    report.legacy_lines += 1;
+                } else if trimmed != record.to_string() {
+                    report.reformatted_lines += 1;
+                }
+                records.push(record);
+            }
+            Ok(None) => report.blank_lines += 1,
+            Err(_) => corrupt.push(line.clone()),
+        }
+    }
+    report.corrupt_lines = corrupt.len();
+
+    let mut unique: Vec<Record> = Vec::new();
+    for mut record in records {
+        if normalize(&mut record) {
+            report.normalized_secrets += 1;
+        }
+        match unique.contains(&record) {
+            true => report.duplicates += 1,
+            false => unique.push(record),
+        }
+    }
+
+    // stable, so records with the same key keep their relative order
+    let before: Vec<String> = unique.iter().map(|r| r.alias.clone()).collect();
+    match order {
+        SortBy::Alias => unique.sort_by_key(|r| r.alias.to_lowercase()),
+        SortBy::Created => unique.sort_by_key(|r| r.created_at),
+    }
+    report.reordered = unique.iter().map(|r| &r.alias).ne(before.iter());
+
+    let mut lines: Vec<String> = unique.iter().map(Record::to_string).collect();
+    lines.extend(corrupt);
+    let compacted = Codex { header: codex.header.clone(), lines };
+    (compacted, report)
+}
+
+// plain text secrets, current and kept ones, the way `add` stores them:
+// upper case without padding. Encrypted secrets are left alone.
+fn normalize(record: &mut Record) -> bool {
+    let canonical = |s: &str| s.trim().to_uppercase().replace('=', "");
+    let mut changed = false;
+    let mut fix = |secret: &mut String, is_unencrypted: bool| {
+        if is_unencrypted && *secret != canonical(secret) {
+            *secret = canonical(secret);
+            changed = true;
+        }
+    };
+
+    fix(&mut record.secret, record.is_unencrypted);
+    for version in &mut record.history {
+        fix(&mut version.secret, version.is_unencrypted);
+    }
+    if let Some(previous) = &mut record.previous {
+        fix(&mut previous.secret, previous.is_unencrypted);
+    }
+    changed
+}
@@ -0,0 +1,196 @@
+use crate::args::{Column, OutputFormat};
+use crate::file;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const PROJECT: &str = "hermes";
+const FILE_CONFIG: &str = "config.toml";
+
+// settings for `config get/set/unset`, vaults have their own commands
+const KEYS: &[&str] = &[
+    "path",
+    "default_vault",
+    "format",
+    "quiet",
+    "columns",
+    "password_command",
+    "backup_retention",
+    "read_only",
+];
+
+/// Settings kept in `config.toml` next to the default codex. Flags and env
+/// vars take precedence over all of them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+    /// Codex used when neither --path, --vault nor their env vars are set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_vault: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<Column>>,
+    /// Shell command printing the password, asked before the prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_retention: Option<usize>,
+    /// Refuse every change to the codex, like `--read-only`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vaults: BTreeMap<String, VaultEntry>,
+}
+
+/// A named codex, selected with `--vault <name>`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultEntry {
+    pub path: PathBuf,
+}
+
+// priority 1 => env var HERMES_CONFIG
+// priority 2 => default location ~/.config/hermes/config.toml
+pub fn config_path() -> PathBuf {
+    std::env::var("HERMES_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            dirs::config_dir()
+                .map(|path| path.join(PROJECT).join(FILE_CONFIG))
+                .expect("Error: Failed to get config path")
+        })
+}
+
+impl Config {
+    /// Reads the config file, a missing file is an empty config.
+    pub fn load() -> Result<Self, String> {
+        let path = config_path();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid config {}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read config {}: {e}", path.display())),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = config_path();
+        let content = toml::to_string(self).map_err(|e| e.to_string())?;
+        file::ensure_dir_exists(&path)
+            .and_then(|_| file::overwrite_file(&path, content.as_bytes()))
+            .map_err(|e| format!("Failed to write config {}: {e}", path.display()))
+    }
+
+    /// The value of key as `config set` takes it, None if unset.
+    pub fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let value = match key {
+            "path" => self.path.as_ref().map(|p| p.display().to_string()),
+            "default_vault" => self.default_vault.clone(),
+            "format" => self.format.and_then(|f| f.to_possible_value()).map(|v| v.get_name().to_string()),
+            "quiet" => self.quiet.map(|q| q.to_string()),
+            "columns" => self.columns.as_ref().map(|c| {
+                c.iter()
+                    .filter_map(|c| c.to_possible_value())
+                    .map(|v| v.get_name().to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            "password_command" => self.password_command.clone(),
+            "backup_retention" => self.backup_retention.map(|n| n.to_string()),
+            "read_only" => Some(self.read_only.to_string()),
+            _ => return Err(unknown_key(key)),
+        };
+        Ok(value)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = |expected: &str| format!("Invalid value '{value}' for {key}, expected {expected}.");
+        let parse_bool = || value.parse::<bool>().map_err(|_| invalid("true or false"));
+
+        match key {
+            "path" => self.path = Some(std::path::absolute(value).map_err(|e| e.to_string())?),
+            "default_vault" => {
+                self.vault_path(value)?;
+                self.default_vault = Some(value.to_string());
+            }
+            "format" => {
+                self.format = Some(OutputFormat::from_str(value, true).map_err(|_| invalid("table or json"))?);
+            }
+            "quiet" => self.quiet = Some(parse_bool()?),
+            "columns" => {
+                self.columns = Some(parse_columns(value)?);
+            }
+            "password_command" => self.password_command = Some(value.to_string()),
+            "backup_retention" => {
+                self.backup_retention = Some(value.parse().map_err(|_| invalid("a number"))?);
+            }
+            "read_only" => self.read_only = parse_bool()?,
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<(), String> {
+        match key {
+            "path" => self.path = None,
+            "default_vault" => self.default_vault = None,
+            "format" => self.format = None,
+            "quiet" => self.quiet = None,
+            "columns" => self.columns = None,
+            "password_command" => self.password_command = None,
+            "backup_retention" => self.backup_retention = None,
+            "read_only" => self.read_only = false,
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    pub fn vault_path(&self, name: &str) -> Result<PathBuf, String> {
+        self.vaults
+            .get(name)
+            .map(|v| v.path.clone())
+            .ok_or_else(|| format!("No vault named '{name}', see `hermes vault list`."))
+    }
+
+    pub fn add_vault(&mut self, name: &str, path: &Path) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err("Vault names may only contain letters, digits, '-' and '_'.".into());
+        }
+        if self.vaults.contains_key(name) {
+            return Err(format!("Vault '{name}' already exists."));
+        }
+
+        // relative paths would change meaning with the working directory
+        let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+        self.vaults.insert(name.to_string(), VaultEntry { path });
+        Ok(())
+    }
+
+    pub fn remove_vault(&mut self, name: &str) -> Result<VaultEntry, String> {
+        let vault = self.vaults
+            .remove(name)
+            .ok_or_else(|| format!("No vault named '{name}'."))?;
+        if self.default_vault.as_deref() == Some(name) {
+            self.default_vault = None;
+        }
+        Ok(vault)
+    }
+}
+
+/// Parses a comma separated column list like `alias,otp,rem`.
+pub fn parse_columns(value: &str) -> Result<Vec<Column>, String> {
+    value
+        .split(',')
+        .map(|c| Column::from_str(c.trim(), true))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid columns '{value}', expected a comma separated list \
+            of alias, issuer, account, tags, otp, previous, created, rem, notes."))
+}
+
+fn unknown_key(key: &str) -> String {
+    format!("Unknown config key '{key}', expected one of: {}.", KEYS.join(", "))
+}
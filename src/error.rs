@@ -0,0 +1,48 @@
+use std::fmt;
+
+/// Exit status when a change is refused because the codex is read-only.
+pub const EXIT_READ_ONLY: i32 = 3;
+/// Exit status when a change is refused because the record is locked.
+pub const EXIT_LOCKED: i32 = 4;
+
+/// An error message and the exit status hermes ends with. Plain `String`
+/// errors convert into it with status 1.
+#[derive(Debug)]
+pub struct Failure {
+    pub message: String,
+    pub code: i32,
+}
+
+impl Failure {
+    pub fn read_only(reason: &str) -> Self {
+        Self {
+            message: format!("The codex is read-only ({reason}), refusing to change it."),
+            code: EXIT_READ_ONLY,
+        }
+    }
+
+    pub fn locked(alias: &str) -> Self {
+        Self {
+            message: format!("Record '{alias}' is locked, unlock it with `hermes unlock {alias}`."),
+            code: EXIT_LOCKED,
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self { message, code: 1 }
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -1,12 +1,22 @@
-use crate::models::Record;
-use dirs;
+use crate::config::Config;
+use crate::models::{Codex, Header, Record};
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, Write};
+use std::fs::TryLockError;
+use std::io::{self, BufRead, Read, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const FILE_CODEX: &str = "codex";
 const PROJECT: &str = "hermes";
+const BACKUPS_DIR: &str = "backups";
+const BACKUP_RETENTION: usize = 10;
+const LOCK_TIMEOUT_SECS: u64 = 10;
+const LOCK_POLL: Duration = Duration::from_millis(50);
+// owner-only modes for everything hermes creates
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o600;
 
 pub fn get_default_path() -> PathBuf {
     // using dirs fn to get location of config directory
@@ -39,25 +49,124 @@ pub fn read_file_to_vec(path: &Path) -> io::Result<Vec<String>> {
         })
 }
 
-pub fn append_to_file(path: &Path, data: &str) -> io::Result<()> {
-    let mut data_file = OpenOptions::new().append(true).open(path)?;
-    writeln!(data_file, "{}", data.trim())
+pub fn read_codex(path: &Path) -> io::Result<Codex> {
+    let mut lines = read_file_to_vec(path)?;
+    let header = lines.first().and_then(|l| Header::from_line(l));
+    if header.is_some() {
+        lines.remove(0);
+    }
+    Ok(Codex { header, lines })
 }
 
-pub fn overwrite_file(path: &Path, data: &str) -> io::Result<()> {
-    std::fs::write(path, data)
+/// The first record holding alias, reading only up to it.
+pub fn find_record(path: &Path, alias: &str) -> io::Result<Option<Record>> {
+    for line in read_lines(path)? {
+        if let Some(record) = Record::from_line(&line?).filter(|r| r.alias == alias) {
+            return Ok(Some(record));
+        }
+    }
+    Ok(None)
 }
 
-pub fn alias_exists(alias: &str, path: &Path) -> bool {
-    read_file_to_vec(path)
-        .map(|lines| {
-            lines.iter().any(|line| {
-                Record::from_line(line)
-                    .map(|r| r.alias == alias)
-                    .unwrap_or(false)
-            })
-        })
-        .unwrap_or(false)
+pub fn write_codex(path: &Path, codex: &Codex) -> io::Result<()> {
+    let mut data = String::new();
+    if let Some(header) = &codex.header {
+        data.push_str(&header.to_line());
+        data.push('\n');
+    }
+    for line in &codex.lines {
+        data.push_str(line);
+        data.push('\n');
+    }
+    write_atomic(path, data.as_bytes())
+}
+
+/// Appends lines to the quarantine file next to the codex.
+pub fn quarantine_lines(path: &Path, lines: &[String]) -> io::Result<PathBuf> {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".quarantine");
+    let quarantine = PathBuf::from(name);
+
+    let mut content = if quarantine.exists() {
+        std::fs::read_to_string(&quarantine)?
+    } else {
+        String::new()
+    };
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    write_atomic(&quarantine, content.as_bytes())?;
+    Ok(quarantine)
+}
+
+pub fn overwrite_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomic(path, data)
+}
+
+/// Appends data to an owner-only file and fsyncs it, creating it if needed.
+pub fn append_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = private_open_options().append(true).create(true).open(path)?;
+    restrict_permissions(&file)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Creates an empty owner-only file, for backends that open the file themselves.
+pub fn create_private_file(path: &Path) -> io::Result<()> {
+    let file = private_open_options().write(true).create_new(true).open(path)?;
+    restrict_permissions(&file)
+}
+
+/// Resets an existing file to owner-only, e.g. after git checked it out.
+pub fn make_private(path: &Path) -> io::Result<()> {
+    restrict_permissions(&File::open(path)?)
+}
+
+/// Writes data to a temp file next to path, fsyncs it and renames it over
+/// path. Either the old or the new content is on disk, never a mix.
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid codex path"))?;
+
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".tmp.{}", std::process::id()));
+    let tmp_path = dir.join(tmp_name);
+
+    let result = (|| {
+        let mut tmp = private_open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        restrict_permissions(&tmp)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        sync_dir(&dir)
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+// persist the rename itself, otherwise a crash can roll the directory entry back
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
 }
 
 pub fn ensure_dir_exists(path: &Path) -> io::Result<()> {
@@ -66,13 +175,194 @@ pub fn ensure_dir_exists(path: &Path) -> io::Result<()> {
         // if the path is just "test.codex", parent() might be Some("") or empty
         // call create_dir_all if the parent isn't empty
         if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)?;
+            let mut builder = std::fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                builder.mode(DIR_MODE);
+            }
+            builder.create(parent)?;
         }
     }
     Ok(())
 }
 
-fn perform_backup(path: &Path, extension: &str) -> io::Result<PathBuf> {
+fn private_open_options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut opts = OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(FILE_MODE);
+    }
+    opts
+}
+
+// mode() only applies on creation, so enforce it on reused files too
+#[cfg(unix)]
+fn restrict_permissions(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(FILE_MODE))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns the codex, its backups and sidecar files that group or others
+/// can access in any way, together with their current mode.
+#[cfg(unix)]
+pub fn find_insecure_files(path: &Path) -> Vec<(PathBuf, u32)> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut candidates = vec![path.to_path_buf()];
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+        for dir in [dir.to_path_buf(), backups_dir(path)] {
+            if let Ok(entries) = std::fs::read_dir(dir) {
+                candidates.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| {
+                    p.file_name()
+                        .map(|n| n.to_string_lossy())
+                        .is_some_and(|n| {
+                            n.starts_with(&prefix)
+                                && [".bak", ".quarantine", ".journal", ".trash", ".audit", ".audit.head"].iter().any(|s| n.ends_with(s))
+                        })
+                }));
+            }
+        }
+    }
+    // e.g. foo.bak of foo.db, which the prefix above misses
+    candidates.extend(legacy_backups(path).into_iter().map(|b| b.path));
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+        .into_iter()
+        .filter_map(|p| {
+            let mode = std::fs::metadata(&p).ok()?.permissions().mode() & 0o777;
+            (mode & 0o077 != 0).then_some((p, mode))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn find_insecure_files(_path: &Path) -> Vec<(PathBuf, u32)> {
+    Vec::new()
+}
+
+/// One backup generation of a codex.
+pub struct Backup {
+    pub id: String,
+    pub path: PathBuf,
+    pub created_at: u64, // Unix timestamp in sec
+}
+
+// backups of all codexes in a directory live in <dir>/backups
+pub fn backups_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.join(BACKUPS_DIR),
+        _ => PathBuf::from(BACKUPS_DIR),
+    }
+}
+
+fn backup_prefix(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| FILE_CODEX.to_string());
+    format!("{name}.")
+}
+
+// at least 1, the backup just taken is never pruned right away
+pub fn backup_retention() -> usize {
+    std::env::var("HERMES_BACKUP_RETENTION")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .or_else(|| Config::load().ok()?.backup_retention)
+        .unwrap_or(BACKUP_RETENTION)
+        .max(1)
+}
+
+/// Lists backup generations of the codex, oldest first. Backups taken by
+/// older hermes versions next to the codex are listed too.
+pub fn list_backups(path: &Path) -> io::Result<Vec<Backup>> {
+    let mut backups = legacy_backups(path);
+    let dir = backups_dir(path);
+    if dir.exists() {
+        let prefix = backup_prefix(path);
+        backups.extend(std::fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                let id = name.strip_prefix(&prefix)?.strip_suffix(".bak")?.to_string();
+                // id is <unix millis>, optionally with a -N suffix on collision
+                let millis: u64 = id.split('-').next()?.parse().ok()?;
+                Some(Backup {
+                    id,
+                    path: e.path(),
+                    created_at: millis / 1000,
+                })
+            }));
+    }
+
+    backups.sort_by_key(backup_sort_key);
+    Ok(backups)
+}
+
+// older versions wrote <codex stem>.bak, and <codex stem>.<unix secs>.bak
+// before a migration, next to the codex. Their ids are legacy and legacy-<secs>.
+fn legacy_backups(path: &Path) -> Vec<Backup> {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let (id, created_at) = match name.strip_prefix(&prefix)? {
+                "bak" => {
+                    let modified = e.metadata().ok()?.modified().ok()?;
+                    ("legacy".to_string(), modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+                }
+                rest => {
+                    let secs = rest.strip_suffix(".bak")?;
+                    (format!("legacy-{secs}"), secs.parse().ok()?)
+                }
+            };
+            Some(Backup { id, path: e.path(), created_at })
+        })
+        .collect()
+}
+
+fn backup_sort_key(backup: &Backup) -> (u64, u64) {
+    let mut parts = backup.id.splitn(2, '-');
+    let millis = parts.next().and_then(|p| p.parse().ok()).unwrap_or(backup.created_at * 1000);
+    let n = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    (millis, n)
+}
+
+pub fn find_backup(path: &Path, id: &str) -> io::Result<Backup> {
+    list_backups(path)?
+        .into_iter()
+        .find(|b| b.id == id)
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No backup with id '{id}'."),
+        ))
+}
+
+/// Copies the codex into a new backup generation and prunes old ones
+/// according to the retention policy.
+pub fn create_backup(path: &Path) -> io::Result<PathBuf> {
     if !path.exists() {
         return Err(io::Error::new(
             io::ErrorKind::NotFound,
@@ -80,24 +370,132 @@ fn perform_backup(path: &Path, extension: &str) -> io::Result<PathBuf> {
         ));
     }
 
-    let mut backup_path = path.to_path_buf();
-    backup_path.set_extension(extension);
+    let dir = backups_dir(path);
+    ensure_dir_exists(&dir.join(FILE_CODEX))?;
 
-    std::fs::copy(path, &backup_path)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis();
+    let prefix = backup_prefix(path);
+    let mut backup_path = dir.join(format!("{prefix}{millis}.bak"));
+    let mut n = 1;
+    while backup_path.exists() {
+        backup_path = dir.join(format!("{prefix}{millis}-{n}.bak"));
+        n += 1;
+    }
+
+    // raw bytes, the codex may be a SQLite database
+    let data = std::fs::read(path)?;
+    write_atomic(&backup_path, &data)?;
+
+    prune_backups(path, backup_retention())?;
     Ok(backup_path)
 }
 
-// routine backups for add and remove cmd
-pub fn create_routine_backup(path: &Path) -> io::Result<PathBuf> {
-    perform_backup(path, "bak")
+/// Removes all but the newest keep generations, returns the removed ones.
+pub fn prune_backups(path: &Path, keep: usize) -> io::Result<Vec<Backup>> {
+    let backups = list_backups(path)?;
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<Backup> = backups.into_iter().take(excess).collect();
+    for b in &removed {
+        std::fs::remove_file(&b.path)?;
+    }
+    Ok(removed)
 }
 
-// backup for migration with UNIX timestamp
-pub fn create_snapshot_backup(path: &Path) -> io::Result<PathBuf> {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs();
+/// Advisory lock on the codex, released when dropped.
+///
+/// The lock lives on a sidecar `<codex>.lock` file, because atomic writes
+/// replace the codex inode and a lock on it would not survive a rewrite.
+pub struct CodexLock {
+    file: File,
+    exclusive: bool,
+}
+
+impl Drop for CodexLock {
+    fn drop(&mut self) {
+        if self.exclusive {
+            // clear our PID before anyone else can grab the lock
+            let _ = self.file.set_len(0);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+// shared lock for reads, many readers at once
+pub fn lock_shared(path: &Path) -> io::Result<CodexLock> {
+    acquire_lock(path, false)
+}
+
+// exclusive lock for mutations, held across the whole read-modify-write
+pub fn lock_exclusive(path: &Path) -> io::Result<CodexLock> {
+    acquire_lock(path, true)
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn lock_timeout() -> Duration {
+    let secs = std::env::var("HERMES_LOCK_TIMEOUT")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(LOCK_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+fn acquire_lock(path: &Path, exclusive: bool) -> io::Result<CodexLock> {
+    let lock_path = lock_path(path);
+    let mut file = private_open_options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+
+    let deadline = Instant::now() + lock_timeout();
+    loop {
+        let attempt = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+
+        match attempt {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                std::thread::sleep(LOCK_POLL);
+            }
+            Err(TryLockError::WouldBlock) => {
+                let holder = match read_lock_holder(&mut file) {
+                    Some(pid) => format!("PID {pid}"),
+                    None => "another process".to_string(),
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("Codex is locked by {holder} ({})", lock_path.display()),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+
+    if exclusive {
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+    }
+
+    Ok(CodexLock { file, exclusive })
+}
 
-    perform_backup(path, &format!("{}.bak", timestamp))
+fn read_lock_holder(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
 }
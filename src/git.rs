@@ -0,0 +1,173 @@
+use crate::file;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const AUTOCOMMIT_KEY: &str = "hermes.autocommit";
+const REMOTE: &str = "origin";
+const GITIGNORE: &str = "*.lock\n.*.tmp.*\n*.quarantine\n*.journal\n*.trash\n*.audit\n*.audit.head\n*.sealed\nbackups/\n";
+
+// the repository is the directory holding the codex
+fn repo_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn codex_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(dir);
+
+    // commits must not fail on machines without a git identity
+    if !has_identity(dir) {
+        cmd.args(["-c", "user.name=hermes", "-c", "user.email=hermes@localhost"]);
+    }
+
+    let output = cmd
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {e}"))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn has_identity(dir: &Path) -> bool {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["config", "user.email"])
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// True when the codex directory is a hermes managed repository.
+pub fn is_enabled(path: &Path) -> bool {
+    let dir = repo_dir(path);
+    dir.join(".git").exists()
+        && git(&dir, &["config", "--get", AUTOCOMMIT_KEY]).is_ok_and(|v| v == "true")
+}
+
+/// Turns the codex directory into a repository with automatic commits.
+pub fn init(path: &Path, remote: Option<&str>) -> Result<(), String> {
+    let dir = repo_dir(path);
+    if !dir.join(".git").exists() {
+        git(&dir, &["init", "--quiet"])?;
+    }
+    git(&dir, &["config", AUTOCOMMIT_KEY, "true"])?;
+
+    if let Some(url) = remote {
+        if git(&dir, &["remote", "get-url", REMOTE]).is_ok() {
+            git(&dir, &["remote", "set-url", REMOTE, url])?;
+        } else {
+            git(&dir, &["remote", "add", REMOTE, url])?;
+        }
+    }
+
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        file::overwrite_file(&gitignore, GITIGNORE.as_bytes()).map_err(|e| e.to_string())?;
+    }
+
+    git(&dir, &["add", ".gitignore"])?;
+    if path.exists() {
+        git(&dir, &["add", &codex_name(path)])?;
+    }
+    commit_staged(&dir, "hermes: track codex")
+}
+
+fn commit_staged(dir: &Path, message: &str) -> Result<(), String> {
+    // nothing staged => nothing to commit
+    if git(dir, &["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(());
+    }
+    git(dir, &["commit", "--quiet", "-m", message]).map(|_| ())
+}
+
+/// Commits the current codex, if automatic commits are enabled.
+pub fn record_change(path: &Path, message: &str) -> Result<(), String> {
+    if !is_enabled(path) {
+        return Ok(());
+    }
+    let dir = repo_dir(path);
+    git(&dir, &["add", &codex_name(path)])?;
+    commit_staged(&dir, message)
+}
+
+pub fn has_remote(path: &Path) -> bool {
+    git(&repo_dir(path), &["remote", "get-url", REMOTE]).is_ok()
+}
+
+pub fn current_branch(path: &Path) -> Result<String, String> {
+    git(&repo_dir(path), &["symbolic-ref", "--short", "HEAD"])
+}
+
+pub fn fetch(path: &Path) -> Result<(), String> {
+    git(&repo_dir(path), &["fetch", "--quiet", REMOTE]).map(|_| ())
+}
+
+pub fn push(path: &Path, branch: &str) -> Result<(), String> {
+    git(&repo_dir(path), &["push", "--quiet", "-u", REMOTE, branch]).map(|_| ())
+}
+
+pub fn remote_ref(branch: &str) -> String {
+    format!("{REMOTE}/{branch}")
+}
+
+pub fn ref_exists(path: &Path, reference: &str) -> bool {
+    git(&repo_dir(path), &["rev-parse", "--verify", "--quiet", reference]).is_ok()
+}
+
+pub fn is_ancestor(path: &Path, ancestor: &str, descendant: &str) -> bool {
+    git(&repo_dir(path), &["merge-base", "--is-ancestor", ancestor, descendant]).is_ok()
+}
+
+pub fn merge_base(path: &Path, a: &str, b: &str) -> Option<String> {
+    git(&repo_dir(path), &["merge-base", a, b]).ok()
+}
+
+pub fn fast_forward(path: &Path, reference: &str) -> Result<(), String> {
+    git(&repo_dir(path), &["merge", "--quiet", "--ff-only", reference]).map(|_| ())
+}
+
+/// Writes the codex as it is at reference into target, false if it does
+/// not exist there.
+pub fn export_codex(path: &Path, reference: &str, target: &Path) -> Result<bool, String> {
+    let dir = repo_dir(path);
+    let spec = format!("{reference}:{}", codex_name(path));
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&dir)
+        .args(["show", &spec])
+        .output()
+        .map_err(|e| format!("Failed to run git: {e}"))?;
+
+    if !output.status.success() {
+        return Ok(false);
+    }
+    file::overwrite_file(target, &output.stdout).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Commits the codex as saved as a merge of HEAD and reference. Built from
+/// plumbing, so a failure never leaves a half-done merge (MERGE_HEAD) that
+/// the next ordinary commit would complete.
+pub fn record_merge(path: &Path, reference: &str, message: &str) -> Result<(), String> {
+    let dir = repo_dir(path);
+    git(&dir, &["add", &codex_name(path)])?;
+    let tree = git(&dir, &["write-tree"])?;
+    let commit = git(&dir, &["commit-tree", &tree, "-p", "HEAD", "-p", reference, "-m", message])?;
+    git(&dir, &["update-ref", "HEAD", &commit]).map(|_| ())
+}
@@ -0,0 +1,114 @@
+use crate::file;
+use crate::models::{Codex, Header};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// domain separation, so the MAC key is never the raw encryption password
+const MAC_KEY_CONTEXT: &[u8] = b"hermes codex mac v1";
+
+fn derive_key(password: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(password.trim().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(MAC_KEY_CONTEXT);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn keyed_mac(password: &str, header: &Header, lines: &[String]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&derive_key(password))
+        .expect("HMAC accepts keys of any size");
+
+    // the header is covered too, minus the mac itself
+    let mut unsealed = header.clone();
+    unsealed.mac = None;
+    mac.update(unsealed.to_line().as_bytes());
+
+    // length-prefix every line, so content cannot move across line breaks
+    for line in lines {
+        mac.update(&(line.len() as u64).to_be_bytes());
+        mac.update(line.as_bytes());
+    }
+    mac
+}
+
+/// Computes the MAC over header and record lines, hex encoded.
+pub fn compute_mac(password: &str, header: &Header, lines: &[String]) -> String {
+    HEXLOWER.encode(&keyed_mac(password, header, lines).finalize().into_bytes())
+}
+
+/// Checks the stored MAC in constant time. Unsealed headers always pass.
+pub fn verify_mac(password: &str, header: &Header, lines: &[String]) -> bool {
+    let Some(stored) = &header.mac else {
+        return true;
+    };
+    let Ok(expected) = HEXLOWER.decode(stored.as_bytes()) else {
+        return false;
+    };
+    keyed_mac(password, header, lines).verify_slice(&expected).is_ok()
+}
+
+/// MAC over a file kept next to a sealed codex, e.g. the journal, with the
+/// key of the codex MAC. context tells the files apart.
+pub fn compute_data_mac(password: &str, context: &str, data: &[u8]) -> String {
+    HEXLOWER.encode(&keyed_data_mac(password, context, data).finalize().into_bytes())
+}
+
+/// Checks a MAC from compute_data_mac in constant time, a missing one fails.
+pub fn verify_data_mac(password: &str, context: &str, data: &[u8], stored: Option<&str>) -> bool {
+    let Some(Ok(expected)) = stored.map(|s| HEXLOWER.decode(s.as_bytes())) else {
+        return false;
+    };
+    keyed_data_mac(password, context, data).verify_slice(&expected).is_ok()
+}
+
+fn keyed_data_mac(password: &str, context: &str, data: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&derive_key(password))
+        .expect("HMAC accepts keys of any size");
+    mac.update(&(context.len() as u64).to_be_bytes());
+    mac.update(context.as_bytes());
+    mac.update(data);
+    mac
+}
+
+// written by `hermes seal`. It lives outside the codex, so deleting the
+// header line does not silently turn a sealed codex into an unsealed one.
+fn marker_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".sealed");
+    PathBuf::from(name)
+}
+
+pub fn is_marked_sealed(path: &Path) -> bool {
+    marker_path(path).exists()
+}
+
+/// Remembers that the codex at path is sealed.
+pub fn mark_sealed(path: &Path) -> Result<(), String> {
+    file::overwrite_file(&marker_path(path), b"")
+        .map_err(|e| format!("Failed to write {}: {e}", marker_path(path).display()))
+}
+
+/// Fails when the codex at path was sealed, but has lost its header or MAC.
+pub fn check_seal_present(path: &Path, codex: &Codex) -> Result<(), String> {
+    let has_mac = codex.header.as_ref().is_some_and(|h| h.is_sealed());
+    match is_marked_sealed(path) && !has_mac {
+        true => Err(format!("Integrity check failed: {} was sealed, but its seal is missing. \
+            Run `hermes seal` if it was removed on purpose.", path.display())),
+        false => Ok(()),
+    }
+}
+
+/// Unkeyed SHA-256 over header and lines, hex encoded. Tells whether a
+/// codex is still in a state hermes saw, not whether it is authentic.
+pub fn fingerprint(codex: &Codex) -> String {
+    let mut hash = Sha256::new();
+    let header = codex.header.as_ref().map(Header::to_line).unwrap_or_default();
+    for line in std::iter::once(&header).chain(&codex.lines) {
+        hash.update((line.len() as u64).to_be_bytes());
+        hash.update(line.as_bytes());
+    }
+    HEXLOWER.encode(&hash.finalize())
+}
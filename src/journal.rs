@@ -0,0 +1,189 @@
+use crate::error::Failure;
+use crate::file;
+use crate::integrity;
+use crate::models::{Codex, Record};
+use crate::vault::Vault;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// oldest operations are dropped beyond this
+const JOURNAL_LIMIT: usize = 100;
+const MAC_CONTEXT: &str = "journal";
+
+/// Operations on the codex that `undo` and `redo` step through. Records are
+/// kept as stored, i.e. encrypted.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+    // entries[..position] are applied, entries[position..] can be redone
+    position: usize,
+    /// Fingerprint of the codex after the last save through hermes
+    digest: Option<String>,
+    /// MAC over the fields above, set while the codex is sealed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mac: Option<String>,
+}
+
+/// One journaled operation with the record before and after it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub op: Op,
+    pub before: Option<Record>,
+    pub after: Option<Record>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Add,
+    Remove,
+    Update,
+    Rename,
+}
+
+impl Entry {
+    pub fn new(op: Op, before: Option<Record>, after: Option<Record>) -> Self {
+        Self { op, before, after }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alias = |r: &Option<Record>| r.as_ref().map(|r| r.alias.clone()).unwrap_or_default();
+        match self.op {
+            Op::Add => write!(f, "add {}", alias(&self.after)),
+            Op::Remove => write!(f, "remove {}", alias(&self.before)),
+            Op::Update => write!(f, "update {}", alias(&self.after)),
+            Op::Rename => write!(f, "rename {} -> {}", alias(&self.before), alias(&self.after)),
+        }
+    }
+}
+
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".journal");
+    PathBuf::from(name)
+}
+
+impl Journal {
+    /// Reads the journal of the codex at path, a missing one is empty.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let journal = journal_path(path);
+        match std::fs::read_to_string(&journal) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid journal {}: {e}", journal.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read journal {}: {e}", journal.display())),
+        }
+    }
+
+    /// Writes the journal, MACed with key when the codex is sealed.
+    pub fn save(&mut self, path: &Path, key: Option<&str>) -> Result<(), String> {
+        self.mac = match key {
+            Some(key) => Some(integrity::compute_data_mac(key, MAC_CONTEXT, &self.covered()?)),
+            None => None,
+        };
+        let content = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        file::overwrite_file(&journal_path(path), content.as_bytes())
+            .map_err(|e| format!("Failed to write journal: {e}"))
+    }
+
+    /// Remembers the state hermes just saved, and the operation that led
+    /// to it. A new operation drops everything that could be redone.
+    pub fn record(&mut self, codex: &Codex, entry: Option<Entry>) {
+        if let Some(entry) = entry {
+            self.entries.truncate(self.position);
+            self.entries.push(entry);
+            if self.entries.len() > JOURNAL_LIMIT {
+                self.entries.drain(..self.entries.len() - JOURNAL_LIMIT);
+            }
+            self.position = self.entries.len();
+        }
+        self.digest = Some(integrity::fingerprint(codex));
+    }
+
+    /// Fails unless the journal carries a valid MAC under key. The digest
+    /// alone is unkeyed, anyone able to write the journal can recompute it.
+    /// Without a key, i.e. for unsealed codexes, this always passes.
+    pub fn verify(&self, key: Option<&str>) -> Result<(), String> {
+        let Some(key) = key else {
+            return Ok(());
+        };
+        match integrity::verify_data_mac(key, MAC_CONTEXT, &self.covered()?, self.mac.as_deref()) {
+            true => Ok(()),
+            false => Err("Integrity check failed: the journal was modified outside hermes, \
+                refusing to replay it.".into()),
+        }
+    }
+
+    // the part of the journal the mac covers
+    fn covered(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(&(&self.entries, self.position, &self.digest)).map_err(|e| e.to_string())
+    }
+
+    // errors unless codex is exactly what hermes saved last
+    fn ensure_unchanged(&self, codex: &Codex) -> Result<(), String> {
+        if self.digest.as_deref() != Some(integrity::fingerprint(codex).as_str()) {
+            return Err("The codex was changed outside hermes since the last recorded \
+                operation, refusing to replay the journal.".into());
+        }
+        Ok(())
+    }
+
+    /// Reverts the last applied operation on vault and returns it.
+    pub fn undo(&mut self, vault: &mut Vault) -> Result<Option<Entry>, Failure> {
+        let Some(entry) = self.position.checked_sub(1).map(|i| self.entries[i].clone()) else {
+            return Ok(None);
+        };
+        self.ensure_unchanged(vault.codex())?;
+        transition(vault, &entry.after, &entry.before)?;
+        self.position -= 1;
+        Ok(Some(entry))
+    }
+
+    /// Applies the next undone operation on vault again and returns it.
+    pub fn redo(&mut self, vault: &mut Vault) -> Result<Option<Entry>, Failure> {
+        let Some(entry) = self.entries.get(self.position).cloned() else {
+            return Ok(None);
+        };
+        self.ensure_unchanged(vault.codex())?;
+        transition(vault, &entry.before, &entry.after)?;
+        self.position += 1;
+        Ok(Some(entry))
+    }
+}
+
+// moves one record from state `from` to state `to`, but only if the codex
+// still holds exactly `from` and it is not locked
+fn transition(vault: &mut Vault, from: &Option<Record>, to: &Option<Record>) -> Result<(), Failure> {
+    let conflict = |alias: &str| format!("Record '{alias}' has changed since, cannot replay.");
+
+    if let Some(f) = from {
+        if vault.get(&f.alias) != Some(f) {
+            return Err(conflict(&f.alias).into());
+        }
+        // also undoing the lock itself, that is what `hermes unlock` is for
+        if f.locked {
+            return Err(Failure::locked(&f.alias));
+        }
+    }
+    if let Some(t) = to {
+        let moves = from.as_ref().is_none_or(|f| f.alias != t.alias);
+        if moves && vault.contains(&t.alias) {
+            return Err(conflict(&t.alias).into());
+        }
+    }
+
+    match (from, to) {
+        (Some(f), Some(t)) => {
+            vault.update(&f.alias, t.clone());
+        }
+        (Some(f), None) => {
+            vault.remove(&f.alias);
+        }
+        (None, Some(t)) => vault.insert(t.clone())?,
+        (None, None) => {}
+    }
+    Ok(())
+}
@@ -1,107 +1,439 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod args;
+mod audit;
 mod cmd;
+mod compact;
+mod config;
+mod error;
 mod file;
+mod git;
+mod integrity;
+mod journal;
+mod merge;
+mod migrations;
 mod models;
 mod otp;
+mod sqlite;
+mod storage;
+mod trash;
 mod ui;
+mod vault;
 
-use args::{Cli, Commands};
-use clap::Parser;
+use args::{AuditAction, BackupAction, Cli, Column, Commands, ConfigAction, OutputFormat, GitAction, NoteAction, TagAction, TrashAction, VaultAction};
+use config::Config;
+use error::Failure;
+use clap::{Parser, ValueEnum};
 
 fn main() {
     let cli = Cli::parse();
-    let codex_path = resolve_codex_path(&cli);
+    let allow_insecure = cli.allow_insecure_permissions
+        || std::env::var("HERMES_ALLOW_INSECURE_PERMISSIONS").is_ok_and(|v| v == "1");
 
-    if let Err(e) = run(cli.command, codex_path) {
+    // the config commands must work even when the config selects no codex
+    if let Commands::Config { action: Some(action) } = cli.command {
+        if let Err(e) = run_config(action) {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let result = resolve_codex_path(&cli).map_err(Failure::from).and_then(|codex_path| {
+        // ls --all-vaults reads every vault, not just the selected one
+        let checked = match cli.command {
+            Commands::Ls { all_vaults: true, .. } => vault_paths()?,
+            _ => vec![codex_path.clone()],
+        };
+        checked.iter().try_for_each(|p| check_permissions(p, allow_insecure))?;
+        if changes_codex(&cli.command) {
+            check_writable(&cli)?;
+        }
+        run(cli.command, codex_path)
+    });
+
+    if let Err(e) = result {
         eprintln!("Error: {e}");
-        std::process::exit(1);
+        std::process::exit(e.code);
     }
 }
 
-fn resolve_codex_path(cli: &Cli) -> PathBuf {
+fn changes_codex(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Add { .. }
+            | Commands::Remove { .. }
+            | Commands::Update { .. }
+            | Commands::Confirm { .. }
+            | Commands::History { purge: true, .. }
+            | Commands::Rollback { .. }
+            | Commands::Lock { .. }
+            | Commands::Unlock { .. }
+            | Commands::Rename { .. }
+            | Commands::Tag { .. }
+            | Commands::Note { action: NoteAction::Set { .. } | NoteAction::Clear { .. } }
+            | Commands::Migrate { .. }
+            | Commands::Compact { dry_run: false, .. }
+            | Commands::Seal { .. }
+            | Commands::Sync { .. }
+            | Commands::Merge { .. }
+            | Commands::Undo { .. }
+            | Commands::Redo { .. }
+            | Commands::Repair { .. }
+            | Commands::Trash { action: TrashAction::Restore { .. } | TrashAction::Purge { .. } }
+            | Commands::Backup { action: BackupAction::Restore { .. } }
+    )
+}
+
+// priority 1 => --read-only
+// priority 2 => env var HERMES_READ_ONLY
+// priority 3 => read_only in the config file
+// a codex locked with `hermes lock` is refused by the command itself, under
+// the codex lock
+fn check_writable(cli: &Cli) -> Result<(), Failure> {
+    if cli.read_only {
+        return Err(Failure::read_only("--read-only"));
+    }
+    if std::env::var("HERMES_READ_ONLY").is_ok_and(|v| v == "1") {
+        return Err(Failure::read_only("HERMES_READ_ONLY"));
+    }
+    if Config::load()?.read_only {
+        return Err(Failure::read_only("config file"));
+    }
+    Ok(())
+}
+
+fn resolve_codex_path(cli: &Cli) -> Result<PathBuf, String> {
     // priority 1 => --path
-    // priority 2 => env var HERMES_PATH
-    // priority 3 => default location ~/.config/hermes/
-    cli.path
-        .clone()
-        .or_else(|| std::env::var("HERMES_PATH").ok().map(PathBuf::from))
-        .unwrap_or_else(|| file::get_default_path())
+    // priority 2 => --vault
+    // priority 3 => env var HERMES_PATH
+    // priority 4 => env var HERMES_VAULT
+    // priority 5 => path of the config file
+    // priority 6 => default vault of the config file
+    // priority 7 => default location ~/.config/hermes/
+    if let Some(path) = &cli.path {
+        return Ok(path.clone());
+    }
+    if let Some(name) = &cli.vault {
+        return Config::load()?.vault_path(name);
+    }
+    if let Ok(path) = std::env::var("HERMES_PATH") {
+        return Ok(PathBuf::from(path));
+    }
+    if let Ok(name) = std::env::var("HERMES_VAULT") {
+        return Config::load()?.vault_path(&name);
+    }
+
+    let config = Config::load()?;
+    if let Some(path) = config.path {
+        return Ok(path);
+    }
+    match &config.default_vault {
+        Some(name) => config.vault_path(name),
+        None => Ok(file::get_default_path()),
+    }
+}
+
+// priority 1 => -f/--format
+// priority 2 => env var HERMES_FORMAT
+// priority 3 => format of the config file
+fn resolve_format(format: Option<OutputFormat>, config: &Config) -> Result<OutputFormat, String> {
+    if let Some(format) = format {
+        return Ok(format);
+    }
+    if let Ok(value) = std::env::var("HERMES_FORMAT") {
+        return OutputFormat::from_str(&value, true)
+            .map_err(|_| format!("Invalid HERMES_FORMAT '{value}', expected table or json."));
+    }
+    Ok(config.format.unwrap_or_default())
 }
 
-fn run(command: Commands, codex_path: PathBuf) -> Result<(), String> {
+// priority 1 => -q/--quiet
+// priority 2 => env var HERMES_QUIET, 1 or 0
+// priority 3 => quiet of the config file
+fn resolve_quiet(quiet: bool, config: &Config) -> bool {
+    if quiet {
+        return true;
+    }
+    match std::env::var("HERMES_QUIET") {
+        Ok(value) => value == "1",
+        Err(_) => config.quiet.unwrap_or(false),
+    }
+}
+
+// priority 1 => --columns
+// priority 2 => env var HERMES_COLUMNS
+// priority 3 => columns of the config file
+fn resolve_columns(columns: Option<Vec<Column>>, config: &Config) -> Result<Vec<Column>, String> {
+    if let Some(columns) = columns {
+        return Ok(columns);
+    }
+    if let Ok(value) = std::env::var("HERMES_COLUMNS") {
+        return config::parse_columns(&value);
+    }
+    Ok(config.columns.clone().unwrap_or_else(|| cmd::DEFAULT_COLUMNS.to_vec()))
+}
+
+fn run_config(action: ConfigAction) -> Result<(), String> {
+    match action {
+        ConfigAction::Get { key } => cmd::config_get(key.as_deref()),
+        ConfigAction::Set { key, value } => cmd::config_set(&key, &value),
+        ConfigAction::Unset { key } => cmd::config_unset(&key),
+        ConfigAction::Edit => cmd::config_edit(),
+    }
+}
+
+fn vault_paths() -> Result<Vec<PathBuf>, String> {
+    Ok(Config::load()?.vaults.into_values().map(|v| v.path).collect())
+}
+
+// refuse codex files with any group/world permission unless explicitly allowed
+fn check_permissions(codex_path: &Path, allow_insecure: bool) -> Result<(), String> {
+    let insecure = file::find_insecure_files(codex_path);
+    if insecure.is_empty() {
+        return Ok(());
+    }
+
+    let list: Vec<String> = insecure
+        .iter()
+        .map(|(p, mode)| format!("  {} (mode {:o})", p.display(), mode))
+        .collect();
+
+    if allow_insecure {
+        eprintln!("Warning: accessible by group or others:\n{}", list.join("\n"));
+        return Ok(());
+    }
+
+    Err(format!(
+        "Refusing to use files accessible by group or others:\n{}\n\
+        Fix with `chmod 600 <file>` or pass --allow-insecure-permissions.",
+        list.join("\n")
+    ))
+}
+
+fn run(command: Commands, codex_path: PathBuf) -> Result<(), Failure> {
     match command {
         Commands::Add {
             alias,
             code,
+            label,
             encryption,
         } => {
-            if alias.contains(":") {
-                return Err("Error: Don't use ':' in alias.".to_string());
-            }
-
+            cmd::ensure_new_alias(&codex_path, &alias)?;
+            let code = cmd::get_effective_code(&code)?;
             cmd::add(
                 &codex_path,
                 &alias,
                 &code,
+                &label,
                 &encryption.unencrypt,
                 &encryption.password,
             )?;
         }
 
-        Commands::Remove { alias } => {
-            cmd::remove(&codex_path, &alias)?; 
+        Commands::Remove { alias, permanent, auth } => {
+            cmd::remove(&codex_path, &alias, permanent, &auth.password)?;
         }
 
         Commands::Update {
             alias,
             code,
+            label,
+            rotation,
             encryption,
         } => {
+            // --issuer/--account alone keep the secret
+            let code = match code.is_none() && label.is_given() {
+                true => None,
+                false => Some(cmd::get_effective_code(&code)?),
+            };
             cmd::update_code(
                 &codex_path,
                 &alias,
-                &code,
+                code.as_deref(),
+                &label,
+                &rotation,
                 &encryption.unencrypt,
                 &encryption.password,
             )?;
         }
 
+        Commands::Confirm { alias, auth } => {
+            cmd::confirm(&codex_path, &alias, &auth.password)?;
+        }
+
+        Commands::History { alias, purge, auth } => match purge {
+            true => cmd::history_purge(&codex_path, &alias, &auth.password)?,
+            false => cmd::history(&codex_path, &alias, &auth.password)?,
+        },
+
+        Commands::Rollback { alias, to, auth } => {
+            cmd::rollback(&codex_path, &alias, to, &auth.password)?;
+        }
+
+        Commands::Lock { alias, auth } => {
+            cmd::lock(&codex_path, alias.as_deref(), true, &auth.password)?;
+        }
+
+        Commands::Unlock { alias, auth } => {
+            cmd::lock(&codex_path, alias.as_deref(), false, &auth.password)?;
+        }
+
         Commands::Rename {
             old_alias,
             new_alias,
+            auth,
         } => {
-            cmd::rename(&codex_path, &old_alias, &new_alias)?;
+            cmd::rename(&codex_path, &old_alias, &new_alias, &auth.password)?;
         }
 
         Commands::Ls {
             alias,
             quiet,
             format,
+            columns,
+            all_vaults,
+            tags,
+            show_notes,
             encryption,
         } => {
-            cmd::ls(
-                &codex_path,
-                &alias,
-                &encryption.unencrypt,
-                &encryption.password,
-                &format,
-                quiet,
-            )?;
+            let config = Config::load()?;
+            let format = resolve_format(format, &config)?;
+            let mut columns = resolve_columns(columns, &config)?;
+            if show_notes && !columns.contains(&Column::Notes) {
+                columns.push(Column::Notes);
+            }
+            match all_vaults {
+                true => cmd::ls_all_vaults(
+                    &config.vaults,
+                    &alias,
+                    &tags,
+                    &encryption,
+                    &format,
+                    &columns,
+                )?,
+                false => cmd::ls(
+                    &codex_path,
+                    &alias,
+                    &tags,
+                    &encryption,
+                    &format,
+                    &columns,
+                    resolve_quiet(quiet, &config),
+                )?,
+            }
+        }
+
+        Commands::Tag { action } => match action {
+            TagAction::Add { alias, tag, auth } => {
+                cmd::tag(&codex_path, &alias, &tag, true, &auth.password)?
+            }
+            TagAction::Remove { alias, tag, auth } => {
+                cmd::tag(&codex_path, &alias, &tag, false, &auth.password)?
+            }
+        },
+
+        Commands::Note { action } => match action {
+            NoteAction::Set { alias, text, auth } => {
+                cmd::note_set(&codex_path, &alias, &text, &auth.password)?
+            }
+            NoteAction::Show { alias, auth } => cmd::note_show(&codex_path, &alias, &auth.password)?,
+            NoteAction::Clear { alias, auth } => cmd::note_clear(&codex_path, &alias, &auth.password)?,
+        },
+
+        Commands::Tags => {
+            cmd::tags(&codex_path)?;
         }
 
-        Commands::Config {} => {
+        Commands::Config { .. } => {
             codex_path.exists()
                 .then(|| println!("{}", codex_path.display()))
                 .ok_or_else(|| format!("Codex file does not exists at {}",
                         codex_path.display()))?;
         }
 
-        Commands::Migrate => {
-            cmd::migrate(&codex_path)
-                .map_err(|e| format!("Migration failed: {e}"))?;
+        Commands::Compact { sort, dry_run, auth } => {
+            cmd::compact(&codex_path, sort, dry_run, &auth.password)?;
+        }
+
+        Commands::Migrate { auth } => {
+            cmd::migrate(&codex_path, &auth.password)
+                .map_err(|e| Failure { message: format!("Migration failed: {e}"), ..e })?;
+        }
+
+        Commands::Seal { auth } => {
+            cmd::seal(&codex_path, &auth.password)?;
         }
+
+        Commands::Audit { action } => match action {
+            AuditAction::Enable => cmd::audit_enable(&codex_path)?,
+            AuditAction::Verify => cmd::audit_verify(&codex_path)?,
+            AuditAction::Show { alias } => cmd::audit_show(&codex_path, alias.as_deref())?,
+        },
+
+        Commands::Git { action } => match action {
+            GitAction::Init { remote } => cmd::git_init(&codex_path, remote.as_deref())?,
+        },
+
+        Commands::Sync { auth } => {
+            cmd::sync(&codex_path, &auth.password)?;
+        }
+
+        Commands::Diff { compare } => {
+            cmd::diff(
+                &codex_path,
+                &compare.other,
+                &compare.auth.password,
+                &compare.other_password,
+            )?;
+        }
+
+        Commands::Merge { compare, strategy } => {
+            cmd::merge(
+                &codex_path,
+                &compare.other,
+                &compare.auth.password,
+                &compare.other_password,
+                strategy,
+            )?;
+        }
+
+        Commands::Convert { dest } => {
+            cmd::convert(&codex_path, &dest)?;
+        }
+
+        Commands::Undo { auth } => {
+            cmd::undo(&codex_path, &auth.password)?;
+        }
+
+        Commands::Redo { auth } => {
+            cmd::redo(&codex_path, &auth.password)?;
+        }
+
+        Commands::Repair { yes, auth } => {
+            cmd::repair(&codex_path, yes, &auth.password)?;
+        }
+
+        Commands::Backup { action } => match action {
+            BackupAction::List => cmd::backup_list(&codex_path)?,
+            BackupAction::Restore { id } => cmd::backup_restore(&codex_path, &id)?,
+            BackupAction::Prune { keep } => cmd::backup_prune(&codex_path, keep)?,
+        },
+
+        Commands::Trash { action } => match action {
+            TrashAction::List => cmd::trash_list(&codex_path)?,
+            TrashAction::Restore { alias, auth } => {
+                cmd::trash_restore(&codex_path, &alias, &auth.password)?
+            }
+            TrashAction::Purge { older_than } => cmd::trash_purge(&codex_path, older_than)?,
+        },
+
+        Commands::Vault { action } => match action {
+            VaultAction::List => cmd::vault_list()?,
+            VaultAction::Add { name, path } => cmd::vault_add(&name, &path)?,
+            VaultAction::Remove { name } => cmd::vault_remove(&name)?,
+            VaultAction::Default { name } => cmd::vault_default(&name)?,
+        },
     }
     Ok(())
 }
@@ -0,0 +1,86 @@
+use crate::models::Record;
+
+/// Three-way merge of two record sets by alias, against their common base.
+///
+/// A side that left a record as it was in base takes the other side's
+/// change (including deletion). When both sides changed a record, the one
+/// changed last wins, ours on a tie. Order follows ours,
+/// records only theirs has are appended in their order.
+pub fn three_way(base: &[Record], ours: &[Record], theirs: &[Record]) -> Vec<Record> {
+    let find = |set: &[Record], alias: &str| set.iter().find(|r| r.alias == alias).cloned();
+
+    let mut aliases: Vec<&str> = ours.iter().map(|r| r.alias.as_str()).collect();
+    for r in theirs {
+        if !aliases.contains(&r.alias.as_str()) {
+            aliases.push(&r.alias);
+        }
+    }
+
+    aliases
+        .into_iter()
+        .filter_map(|alias| {
+            let b = find(base, alias);
+            let o = find(ours, alias);
+            let t = find(theirs, alias);
+            match (o, t) {
+                (Some(o), Some(t)) if o == t => Some(o),
+                (Some(o), Some(t)) => Some(if b.as_ref() == Some(&o) {
+                    t
+                } else if b.as_ref() == Some(&t) {
+                    o
+                } else {
+                    newer(o, t)
+                }),
+                // deleted on their side, keep ours only if we changed it
+                (Some(o), None) => (b.as_ref() != Some(&o)).then_some(o),
+                (None, Some(t)) => (b.as_ref() != Some(&t)).then_some(t),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+/// Picks the record changed last, ours on a tie. Within the same second a
+/// changed record beats one that never was.
+pub fn newer(ours: Record, theirs: Record) -> Record {
+    let key = |r: &Record| (r.changed_at(), r.updated_at.is_some());
+    if key(&theirs) > key(&ours) { theirs } else { ours }
+}
+
+/// The first locked record of ours that result changes or drops.
+pub fn changed_locked<'a>(ours: &'a [Record], result: &[Record]) -> Option<&'a Record> {
+    ours.iter()
+        .filter(|o| o.locked)
+        .find(|o| result.iter().find(|r| r.alias == o.alias) != Some(*o))
+}
+
+/// Difference of one record between two codexes.
+pub enum Change {
+    /// Only in the other codex.
+    Added(Record),
+    /// Only in ours.
+    Removed(Record),
+    // boxed, records are large
+    Changed { ours: Box<Record>, theirs: Box<Record> },
+}
+
+/// Two-way comparison by alias, same decides whether two records with the
+/// same alias are equal. Order follows ours, additions come last.
+pub fn diff(ours: &[Record], theirs: &[Record], same: impl Fn(&Record, &Record) -> bool) -> Vec<Change> {
+    let mut changes: Vec<Change> = ours
+        .iter()
+        .filter_map(|o| match theirs.iter().find(|t| t.alias == o.alias) {
+            None => Some(Change::Removed(o.clone())),
+            Some(t) if !same(o, t) => Some(Change::Changed { ours: Box::new(o.clone()), theirs: Box::new(t.clone()) }),
+            Some(_) => None,
+        })
+        .collect();
+
+    changes.extend(
+        theirs
+            .iter()
+            .filter(|t| !ours.iter().any(|o| o.alias == t.alias))
+            .map(|t| Change::Added(t.clone())),
+    );
+    changes
+}
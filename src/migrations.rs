@@ -0,0 +1,102 @@
+use crate::models::{Codex, Header, Record, CIPHER, CODEX_VERSION};
+
+/// One step in the codex format history, upgrades `from` to `from + 1`.
+struct Migration {
+    from: u32,
+    description: &'static str,
+    apply: fn(Codex) -> Result<Codex, String>,
+}
+
+// ordered chain, every format version below CODEX_VERSION needs one entry
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "convert legacy colon-separated lines to JSON",
+        apply: legacy_to_json,
+    },
+    Migration {
+        from: 1,
+        description: "add versioned codex header",
+        apply: add_header,
+    },
+];
+
+/// Format version of a codex:
+/// 0 => legacy colon-separated lines (possibly mixed with JSON)
+/// 1 => JSON lines without header
+/// 2 => header line with version and vault settings
+pub fn detect_version(codex: &Codex) -> u32 {
+    if let Some(header) = codex.header.as_ref().filter(|h| h.version > 0) {
+        return header.version;
+    }
+
+    if has_legacy_lines(codex) { 0 } else { 1 }
+}
+
+fn has_legacy_lines(codex: &Codex) -> bool {
+    codex.lines.iter().any(|l| {
+        let trimmed = l.trim();
+        !trimmed.is_empty() && !trimmed.starts_with('{') && Record::from_line(trimmed).is_some()
+    })
+}
+
+/// True when the header marks the codex as migrated to JSON lines and no
+/// legacy line crept back in since. Only then may aliases contain ':'.
+pub fn is_json_only(codex: &Codex) -> bool {
+    codex.header.as_ref().is_some_and(|h| h.version >= 2) && !has_legacy_lines(codex)
+}
+
+pub fn ensure_supported(codex: &Codex) -> Result<(), String> {
+    let version = detect_version(codex);
+    if version > CODEX_VERSION {
+        return Err(format!(
+            "Codex format version {version} is newer than this hermes supports \
+            ({CODEX_VERSION}). Upgrade hermes to modify it."
+        ));
+    }
+    Ok(())
+}
+
+/// Runs every migration from the detected version up to CODEX_VERSION,
+/// returns the upgraded codex and the descriptions of the applied steps.
+pub fn migrate(mut codex: Codex) -> Result<(Codex, Vec<String>), String> {
+    ensure_supported(&codex)?;
+
+    let mut applied = Vec::new();
+    let mut version = detect_version(&codex);
+    while version < CODEX_VERSION {
+        let step = MIGRATIONS
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| format!("No migration from format version {version}"))?;
+
+        codex = (step.apply)(codex)?;
+        applied.push(format!("v{} -> v{}: {}", step.from, step.from + 1, step.description));
+        version += 1;
+    }
+
+    Ok((codex, applied))
+}
+
+fn legacy_to_json(mut codex: Codex) -> Result<Codex, String> {
+    // never silently drop a line we cannot parse
+    if let Some((n, e)) = codex.corrupt_lines().first() {
+        return Err(format!("line {n}: {e}. Run `hermes repair` first."));
+    }
+
+    // re-serialize every record line to a JSON string, drop blank lines
+    codex.lines = codex.lines
+        .iter()
+        .filter_map(|l| Record::from_line(l))
+        .map(|r| serde_json::to_string(&r).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    Ok(codex)
+}
+
+fn add_header(mut codex: Codex) -> Result<Codex, String> {
+    // keeps an existing mac, it is recomputed by the caller
+    let header = codex.header.get_or_insert_with(Header::default);
+    header.version = 2;
+    header.cipher.get_or_insert_with(|| CIPHER.to_string());
+    Ok(codex)
+}
@@ -2,56 +2,194 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Record {
     pub alias: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Groups such as infra or finance, lower case, in the order added
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Note>,
     pub secret: String,
     pub is_unencrypted: bool, // only for DEBUG, store secret unencrypted
     pub algorithm: String,
     pub created_at: u64, // Unix timestamp in sec
+    /// Last change after creation, compared by `merge` and `sync`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    /// Previous secrets, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<SecretVersion>,
+    /// Outgoing secret during a rotation grace period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PreviousSecret>,
+    /// Locked records refuse remove, update and rename until `hermes unlock`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+}
+
+/// The secret replaced by the last update, shown next to the new one while
+/// the old registration may still be active.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PreviousSecret {
+    pub secret: String,
+    pub is_unencrypted: bool,
+    /// End of the grace period, None keeps it until `hermes confirm`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+/// Free text kept with a record, e.g. its recovery procedure. Encrypted
+/// with the password of the secret unless that is stored in plain text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Note {
+    pub text: String,
+    pub is_unencrypted: bool,
+}
+
+/// A secret the record held before an update, stored as it was.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SecretVersion {
+    pub secret: String,
+    pub is_unencrypted: bool,
+    pub replaced_at: u64, // Unix timestamp in sec
+}
+
+/// Current Unix timestamp in sec.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
 
 impl Record {
     pub fn new(alias: String, secret: String, is_unencrypted: bool) -> Self {
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs();
-
         Self {
             alias,
+            issuer: None,
+            account: None,
+            tags: Vec::new(),
+            notes: None,
             secret,
             is_unencrypted,
             algorithm: "sha1".to_string(),
-            created_at: since_the_epoch,
+            created_at: now(),
+            updated_at: None,
+            history: Vec::new(),
+            previous: None,
+            locked: false,
+        }
+    }
+
+    /// Marks the record as changed now.
+    pub fn touch(&mut self) {
+        self.updated_at = Some(now());
+    }
+
+    /// Time of the last change, created_at for a record never changed.
+    pub fn changed_at(&self) -> u64 {
+        self.updated_at.unwrap_or(self.created_at)
+    }
+
+    /// Replaces every encrypted field, the secret, its history, the outgoing
+    /// secret and the note, with f of it.
+    /// Used to move records between passwords; the flags stay as they are.
+    pub fn map_encrypted<E>(&mut self, mut f: impl FnMut(&str) -> Result<String, E>) -> Result<(), E> {
+        if !self.is_unencrypted {
+            self.secret = f(&self.secret)?;
+        }
+        for version in self.history.iter_mut().filter(|v| !v.is_unencrypted) {
+            version.secret = f(&version.secret)?;
         }
+        if let Some(previous) = self.previous.as_mut().filter(|p| !p.is_unencrypted) {
+            previous.secret = f(&previous.secret)?;
+        }
+        if let Some(note) = self.notes.as_mut().filter(|n| !n.is_unencrypted) {
+            note.text = f(&note.text)?;
+        }
+        Ok(())
+    }
+
+    /// The outgoing secret, unless its grace period ended before now.
+    pub fn active_previous(&self, now: u64) -> Option<&PreviousSecret> {
+        self.previous.as_ref().filter(|p| p.until.is_none_or(|until| until > now))
+    }
+
+    /// Sets a new secret and moves the current one into history, which is
+    /// then capped to the newest limit versions.
+    pub fn replace_secret(&mut self, secret: String, is_unencrypted: bool, limit: usize) {
+        self.history.push(SecretVersion {
+            secret: std::mem::replace(&mut self.secret, secret),
+            is_unencrypted: std::mem::replace(&mut self.is_unencrypted, is_unencrypted),
+            replaced_at: now(),
+        });
+        let excess = self.history.len().saturating_sub(limit);
+        self.history.drain(..excess);
+    }
+
+    /// Makes version n (1 = the most recently replaced secret) current
+    /// again. The current secret goes into history, so it can be rolled
+    /// back to as well. A pending grace period ends, its code belongs to
+    /// the rotation being undone.
+    pub fn rollback(&mut self, n: usize) -> Result<(), String> {
+        let index = self.history.len().checked_sub(n).filter(|_| n > 0)
+            .ok_or_else(|| format!("'{}' has no version {n}, see `hermes history {}`.", self.alias, self.alias))?;
+
+        let version = self.history.remove(index);
+        let limit = self.history.len() + 1;
+        self.replace_secret(version.secret, version.is_unencrypted, limit);
+        self.previous = None;
+        Ok(())
     }
 
     /// Attempts to parse line into a Record, supports both JSON and Legacy (text)
     pub fn from_line(line: &str) -> Option<Self> {
+        Self::parse_line(line).ok().flatten()
+    }
+
+    /// Like from_line, but tells blank/header lines (Ok(None)) apart from
+    /// lines that cannot be parsed (Err with the reason).
+    pub fn parse_line(line: &str) -> Result<Option<Self>, String> {
         let trimmed = line.trim();
-        if trimmed.is_empty() {
-            return None;
+        if trimmed.is_empty() || Header::from_line(trimmed).is_some() {
+            return Ok(None);
         }
 
-        // try JSON first
-        if let Ok(record) = serde_json::from_str::<Record>(trimmed) {
-            return Some(record);
+        // JSON lines never fall back to the legacy parser
+        if trimmed.starts_with('{') {
+            return serde_json::from_str::<Record>(trimmed)
+                .map(Some)
+                .map_err(|e| format!("invalid JSON record: {e}"));
         }
 
         // fallback to Legacy (text), divider is colon
         let parts: Vec<&str> = trimmed.split(':').collect();
         if parts.len() >= 4 {
-            return Some(Record {
+            return Ok(Some(Record {
                 alias: parts[0].to_string(),
+                issuer: None,
+                account: None,
+                tags: Vec::new(),
+                notes: None,
                 secret: parts[1].to_string(),
                 is_unencrypted: parts[2] == "1",
                 algorithm: parts[3].to_string(),
                 created_at: 0,
-            });
+                updated_at: None,
+                history: Vec::new(),
+                previous: None,
+                locked: false,
+            }));
         }
-        None
+        Err(format!(
+            "expected JSON or legacy 'alias:secret:flag:algorithm', found {} field(s)",
+            parts.len()
+        ))
     }
 }
 
@@ -62,3 +200,100 @@ impl fmt::Display for Record {
         write!(f, "{}", json)
     }
 }
+
+/// Codex format version written by this build, see `migrations`.
+pub const CODEX_VERSION: u32 = 2;
+/// Secret encryption used by `otp::encrypt`.
+pub const CIPHER: &str = "magic-crypt-aes256";
+
+/// Codex header, stored as the first line: `{"hermes":{...}}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Header {
+    /// Codex format version, 0 for headers written before versioning
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+    /// HMAC-SHA256 over the header (without mac) and all record lines, hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /// Refuse every change until `hermes unlock`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HeaderLine {
+    hermes: Header,
+}
+
+impl Header {
+    /// Header for a codex created by this build.
+    pub fn current() -> Self {
+        Self {
+            version: CODEX_VERSION,
+            cipher: Some(CIPHER.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_line(line: &str) -> Option<Self> {
+        serde_json::from_str::<HeaderLine>(line.trim())
+            .ok()
+            .map(|h| h.hermes)
+    }
+
+    pub fn to_line(&self) -> String {
+        let line = HeaderLine { hermes: self.clone() };
+        serde_json::to_string(&line).expect("Failed to serialize header")
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.mac.is_some()
+    }
+}
+
+/// Codex contents: an optional header, then one entry per record line.
+/// Entries are kept verbatim, so unparseable lines survive a rewrite.
+#[derive(Debug, Clone, Default)]
+pub struct Codex {
+    pub header: Option<Header>,
+    pub lines: Vec<String>,
+}
+
+impl Codex {
+    // line number of lines[0] in the file
+    fn first_line_number(&self) -> usize {
+        if self.header.is_some() { 2 } else { 1 }
+    }
+
+    /// Unparseable lines as (line number in the file, reason).
+    pub fn corrupt_lines(&self) -> Vec<(usize, String)> {
+        let first = self.first_line_number();
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| Record::parse_line(l).err().map(|e| (i + first, e)))
+            .collect()
+    }
+
+    /// Index of the first entry holding alias.
+    pub fn position(&self, alias: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|l| Record::from_line(l).is_some_and(|r| r.alias == alias))
+    }
+
+    pub fn records(&self) -> Vec<Record> {
+        self.lines.iter().filter_map(|l| Record::from_line(l)).collect()
+    }
+
+    /// Removes unparseable lines from the codex and returns them.
+    pub fn take_corrupt_lines(&mut self) -> Vec<String> {
+        let (bad, good) = std::mem::take(&mut self.lines)
+            .into_iter()
+            .partition(|l| Record::parse_line(l).is_err());
+        self.lines = good;
+        bad
+    }
+}
@@ -20,7 +20,7 @@ fn get_current_timestamp() -> Result<u64, OtpError> {
 pub fn get_remaining_seconds() -> u64 {
     let now = get_current_timestamp().unwrap_or(0);
     // DEFAULT_STEP == 30s
-    DEFAULT_STEP as u64 - (now % DEFAULT_STEP as u64)
+    DEFAULT_STEP - (now % DEFAULT_STEP)
 }
 
 /*
@@ -51,3 +51,93 @@ pub fn decrypt(encrypted_code: &str, password: &str) -> Result<String, OtpError>
     mc.decrypt_base64_to_string(encrypted_code)
         .map_err(|_| OtpError::DecryptionFailed)
 }
+
+/// Issuer and account of an otpauth label like `GitHub:me@example.com`.
+#[derive(Debug, Default, Clone)]
+pub struct Label {
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+/// Secret and label of an otpauth:// URI, as encoded in enrollment QR codes.
+pub struct OtpAuth {
+    pub secret: String,
+    pub label: Label,
+}
+
+/// Splits an otpauth label into issuer and account, a label without ':'
+/// is only an account.
+pub fn split_label(label: &str) -> Label {
+    let non_empty = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+    match label.split_once(':') {
+        Some((issuer, account)) => Label { issuer: non_empty(issuer), account: non_empty(account) },
+        None => Label { issuer: None, account: non_empty(label) },
+    }
+}
+
+/// Parses `otpauth://totp/Issuer:account?secret=...&issuer=...`. Only
+/// what hermes generates is accepted: TOTP with SHA1, 6 digits and 30s.
+pub fn parse_otpauth(uri: &str) -> Result<OtpAuth, String> {
+    // never echo the URI, it holds the secret
+    let invalid = || "Invalid otpauth URI, expected otpauth://totp/<label>?secret=...".to_string();
+    let rest = uri.strip_prefix("otpauth://").ok_or_else(invalid)?;
+    let (kind, rest) = rest.split_once('/').ok_or_else(invalid)?;
+    if !kind.eq_ignore_ascii_case("totp") {
+        return Err(format!("Only TOTP otpauth URIs are supported, found '{kind}'."));
+    }
+
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let mut label = split_label(&percent_decode(label));
+    let mut secret = None;
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        let supported = match key.to_lowercase().as_str() {
+            "secret" => {
+                secret = Some(value.clone());
+                true
+            }
+            // the parameter wins over the label prefix, as the spec asks
+            "issuer" => {
+                if !value.trim().is_empty() {
+                    label.issuer = Some(value.trim().to_string());
+                }
+                true
+            }
+            "algorithm" => value.eq_ignore_ascii_case("sha1"),
+            "digits" => value == "6",
+            "period" => value == "30",
+            _ => true,
+        };
+        if !supported {
+            return Err(format!("Unsupported otpauth parameter {key}={value}, hermes only \
+                generates SHA1 codes with 6 digits every 30s."));
+        }
+    }
+
+    let secret = secret.filter(|s| !s.is_empty()).ok_or("No secret in otpauth URI.")?;
+    Ok(OtpAuth { secret, label })
+}
+
+// %XX escapes to bytes, malformed escapes are kept as they are
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,167 @@
+use crate::file;
+use crate::models::{Codex, Header, Record};
+use crate::storage::{Storage, Transaction};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+// entries keep the verbatim line in data, alias is NULL when it cannot be parsed
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        position INTEGER PRIMARY KEY,
+        alias    TEXT,
+        data     TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS entries_alias ON entries(alias);
+";
+
+const HEADER_KEY: &str = "header";
+
+/// Embedded SQLite codex with real transactions and an alias index.
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            // create it ourselves, so the database gets owner-only permissions
+            file::ensure_dir_exists(path).map_err(|e| e.to_string())?;
+            file::create_private_file(path).map_err(|e| e.to_string())?;
+        }
+
+        let conn = Connection::open(path).map_err(sql_err)?;
+        conn.execute_batch(SCHEMA).map_err(sql_err)?;
+        Ok(Self { conn })
+    }
+}
+
+fn sql_err(e: rusqlite::Error) -> String {
+    format!("SQLite error: {e}")
+}
+
+fn alias_of(line: &str) -> Option<String> {
+    Record::from_line(line).map(|r| r.alias)
+}
+
+fn load_codex(conn: &Connection) -> Result<Codex, String> {
+    let header = conn
+        .query_row("SELECT value FROM meta WHERE key = ?1", [HEADER_KEY], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()
+        .map_err(sql_err)?
+        .and_then(|line| Header::from_line(&line));
+
+    let mut stmt = conn
+        .prepare("SELECT data FROM entries ORDER BY position")
+        .map_err(sql_err)?;
+    let lines = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(sql_err)?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(sql_err)?;
+
+    Ok(Codex { header, lines })
+}
+
+impl Storage for SqliteStorage {
+    fn kind(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn load(&self) -> Result<Codex, String> {
+        load_codex(&self.conn)
+    }
+
+    fn get(&self, alias: &str) -> Result<Option<Record>, String> {
+        let data: Option<String> = self.conn
+            .query_row(
+                "SELECT data FROM entries WHERE alias = ?1 ORDER BY position LIMIT 1",
+                [alias],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_err)?;
+        Ok(data.and_then(|d| Record::from_line(&d)))
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Transaction) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(sql_err)?;
+        let mut txn = SqliteTransaction { tx: &tx };
+
+        // dropping tx without commit rolls everything back
+        f(&mut txn)?;
+        tx.commit().map_err(sql_err)
+    }
+}
+
+/// Runs every change as SQL inside the transaction.
+struct SqliteTransaction<'a> {
+    tx: &'a rusqlite::Transaction<'a>,
+}
+
+impl SqliteTransaction<'_> {
+    fn insert_line(&self, line: &str) -> Result<(), String> {
+        self.tx
+            .execute(
+                "INSERT INTO entries (position, alias, data)
+                 VALUES ((SELECT COALESCE(MAX(position), 0) + 1 FROM entries), ?1, ?2)",
+                params![alias_of(line), line],
+            )
+            .map_err(sql_err)?;
+        Ok(())
+    }
+}
+
+impl Transaction for SqliteTransaction<'_> {
+    fn insert(&mut self, record: &Record) -> Result<(), String> {
+        self.insert_line(&record.to_string())
+    }
+
+    fn update(&mut self, alias: &str, record: &Record) -> Result<bool, String> {
+        // only the first entry, like the file backend
+        let changed = self.tx
+            .execute(
+                "UPDATE entries SET alias = ?1, data = ?2 WHERE position =
+                 (SELECT MIN(position) FROM entries WHERE alias = ?3)",
+                params![record.alias, record.to_string(), alias],
+            )
+            .map_err(sql_err)?;
+        Ok(changed > 0)
+    }
+
+    fn delete(&mut self, alias: &str) -> Result<bool, String> {
+        let deleted = self.tx
+            .execute("DELETE FROM entries WHERE alias = ?1", [alias])
+            .map_err(sql_err)?;
+        Ok(deleted > 0)
+    }
+
+    fn set_header(&mut self, header: Option<Header>) -> Result<(), String> {
+        match &header {
+            Some(h) => self.tx.execute(
+                "INSERT INTO meta (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![HEADER_KEY, h.to_line()],
+            ),
+            None => self.tx.execute("DELETE FROM meta WHERE key = ?1", [HEADER_KEY]),
+        }
+        .map_err(sql_err)?;
+        Ok(())
+    }
+
+    fn replace_all(&mut self, codex: Codex) -> Result<(), String> {
+        self.tx.execute("DELETE FROM entries", []).map_err(sql_err)?;
+        for line in &codex.lines {
+            self.insert_line(line)?;
+        }
+        self.set_header(codex.header)
+    }
+}
@@ -0,0 +1,169 @@
+use crate::file;
+use crate::models::{Codex, Header, Record};
+use crate::sqlite::SqliteStorage;
+use std::cell::RefCell;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const SQLITE_EXTENSIONS: &[&str] = &["db", "sqlite", "sqlite3"];
+
+/// Persistence behind `Vault`, the on-disk format is up to the
+/// implementation.
+pub trait Storage {
+    /// Short name of the backend, shown to the user
+    fn kind(&self) -> &'static str;
+    /// Loads the header and every entry in stored order.
+    fn load(&self) -> Result<Codex, String>;
+    /// Looks up a single record by alias, the first one if there are several.
+    fn get(&self, alias: &str) -> Result<Option<Record>, String>;
+    /// True when a transaction rewrites the whole codex anyway, so it is
+    /// handed the final codex instead of every single change.
+    fn rewrites_whole(&self) -> bool {
+        false
+    }
+    /// Runs f in a transaction: either all of its changes are persisted or
+    /// none of them are.
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Transaction) -> Result<(), String>,
+    ) -> Result<(), String>;
+}
+
+/// Changes made inside `Storage::transaction`.
+pub trait Transaction {
+    fn insert(&mut self, record: &Record) -> Result<(), String>;
+    /// Replaces the record stored under alias in place, false if missing.
+    fn update(&mut self, alias: &str, record: &Record) -> Result<bool, String>;
+    /// Deletes every entry holding alias, false if there was none.
+    fn delete(&mut self, alias: &str) -> Result<bool, String>;
+    fn set_header(&mut self, header: Option<Header>) -> Result<(), String>;
+    /// Replaces the whole codex, for rewrites like migrate or repair.
+    fn replace_all(&mut self, codex: Codex) -> Result<(), String>;
+}
+
+/// Opens the codex at path with the matching backend. Existing files are
+/// recognized by content, new ones by extension (.db, .sqlite, .sqlite3).
+pub fn open(path: &Path) -> Result<Box<dyn Storage>, String> {
+    if is_sqlite(path) {
+        Ok(Box::new(SqliteStorage::open(path)?))
+    } else {
+        Ok(Box::new(FileStorage::new(path)))
+    }
+}
+
+fn is_sqlite(path: &Path) -> bool {
+    if path.exists() {
+        let mut magic = [0u8; 16];
+        return std::fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok_and(|_| magic == SQLITE_MAGIC);
+    }
+
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SQLITE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// The line-oriented text codex: optional header line, then JSON lines.
+pub struct FileStorage {
+    path: PathBuf,
+    // last state read or written, so a transaction after load does not
+    // parse the file again; callers hold the codex lock in between
+    cached: RefCell<Option<Codex>>,
+}
+
+impl FileStorage {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), cached: RefCell::new(None) }
+    }
+}
+
+impl Storage for FileStorage {
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn load(&self) -> Result<Codex, String> {
+        if let Some(codex) = self.cached.borrow().as_ref() {
+            return Ok(codex.clone());
+        }
+
+        let codex = match file::file_exists(&self.path) {
+            true => file::read_codex(&self.path).map_err(|e| e.to_string())?,
+            false => Codex::default(),
+        };
+        *self.cached.borrow_mut() = Some(codex.clone());
+        Ok(codex)
+    }
+
+    fn rewrites_whole(&self) -> bool {
+        true
+    }
+
+    fn get(&self, alias: &str) -> Result<Option<Record>, String> {
+        if let Some(codex) = self.cached.borrow().as_ref() {
+            return Ok(codex.position(alias).and_then(|i| Record::from_line(&codex.lines[i])));
+        }
+        match file::file_exists(&self.path) {
+            true => file::find_record(&self.path, alias).map_err(|e| e.to_string()),
+            false => Ok(None),
+        }
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Transaction) -> Result<(), String>,
+    ) -> Result<(), String> {
+        // changes stay in memory until the single atomic rewrite below
+        let mut txn = MemoryTransaction { codex: self.load()? };
+        f(&mut txn)?;
+        file::write_codex(&self.path, &txn.codex)
+            .map_err(|e| format!("Error: Failed to save changes: {e}"))?;
+        *self.cached.get_mut() = Some(txn.codex);
+        Ok(())
+    }
+}
+
+/// Transaction on an in-memory codex, written out by `FileStorage` at the end.
+struct MemoryTransaction {
+    codex: Codex,
+}
+
+impl Transaction for MemoryTransaction {
+    fn insert(&mut self, record: &Record) -> Result<(), String> {
+        self.codex.lines.push(record.to_string());
+        Ok(())
+    }
+
+    fn update(&mut self, alias: &str, record: &Record) -> Result<bool, String> {
+        match self.codex.position(alias) {
+            Some(i) => {
+                self.codex.lines[i] = record.to_string();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn delete(&mut self, alias: &str) -> Result<bool, String> {
+        // keeps unparseable lines untouched
+        let original_len = self.codex.lines.len();
+        self.codex.lines.retain(|l| {
+            Record::from_line(l)
+                .map(|r| r.alias != alias)
+                .unwrap_or(true)
+        });
+        Ok(self.codex.lines.len() != original_len)
+    }
+
+    fn set_header(&mut self, header: Option<Header>) -> Result<(), String> {
+        self.codex.header = header;
+        Ok(())
+    }
+
+    fn replace_all(&mut self, codex: Codex) -> Result<(), String> {
+        self.codex = codex;
+        Ok(())
+    }
+}
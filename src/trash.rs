@@ -0,0 +1,114 @@
+use crate::file;
+use crate::integrity;
+use crate::models::Record;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAC_CONTEXT: &str = "trash";
+
+/// A removed record, kept in `<codex>.trash` until it is purged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trashed {
+    pub deleted_at: u64, // Unix timestamp in sec
+    pub record: Record,
+    /// MAC over the entry, set when it was trashed from a sealed codex.
+    /// Each entry has its own, so purging needs no password.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+impl Trashed {
+    /// A record removed now, MACed with key when the codex is sealed.
+    pub fn new(record: Record, key: Option<&str>) -> Self {
+        let deleted_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let mut item = Self { deleted_at, record, mac: None };
+        if let Some(key) = key {
+            item.seal(key);
+        }
+        item
+    }
+
+    pub fn seal(&mut self, key: &str) {
+        self.mac = Some(integrity::compute_data_mac(key, MAC_CONTEXT, &self.covered()));
+    }
+
+    /// True unless the codex is sealed (key given) and the entry's MAC is
+    /// missing or wrong.
+    pub fn verify(&self, key: Option<&str>) -> bool {
+        key.is_none_or(|key| integrity::verify_data_mac(key, MAC_CONTEXT, &self.covered(), self.mac.as_deref()))
+    }
+
+    // the part of the entry the mac covers
+    fn covered(&self) -> Vec<u8> {
+        serde_json::to_vec(&(self.deleted_at, &self.record)).expect("records serialize")
+    }
+}
+
+pub fn trash_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".trash");
+    PathBuf::from(name)
+}
+
+/// Reads the trash of the codex at path, oldest first. A missing trash is
+/// empty.
+pub fn load(path: &Path) -> Result<Vec<Trashed>, String> {
+    let trash = trash_path(path);
+    let content = match std::fs::read_to_string(&trash) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read trash {}: {e}", trash.display())),
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            serde_json::from_str(l)
+                .map_err(|e| format!("Invalid trash {} line {}: {e}", trash.display(), i + 1))
+        })
+        .collect()
+}
+
+pub fn save(path: &Path, items: &[Trashed]) -> Result<(), String> {
+    let mut content = String::new();
+    for item in items {
+        content.push_str(&serde_json::to_string(item).map_err(|e| e.to_string())?);
+        content.push('\n');
+    }
+    file::overwrite_file(&trash_path(path), content.as_bytes())
+        .map_err(|e| format!("Failed to write trash: {e}"))
+}
+
+/// Drops the newest trashed copy of record, e.g. once `undo` brought it
+/// back. False if the trash holds none.
+pub fn take(path: &Path, record: &Record) -> Result<bool, String> {
+    let mut items = load(path)?;
+    let Some(i) = items.iter().rposition(|t| t.record == *record) else {
+        return Ok(false);
+    };
+    items.remove(i);
+    save(path, &items)?;
+    Ok(true)
+}
+
+/// First free alias for a restored record: alias itself, then
+/// alias-restored, alias-restored-2, ...
+pub fn free_alias(alias: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(alias) {
+        return alias.to_string();
+    }
+    let base = format!("{alias}-restored");
+    let mut candidate = base.clone();
+    let mut n = 2;
+    while taken(&candidate) {
+        candidate = format!("{base}-{n}");
+        n += 1;
+    }
+    candidate
+}
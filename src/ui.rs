@@ -1,5 +1,29 @@
 use std::io::{self, Write};
 
+// asks a yes/no question on stderr, anything but y/yes is a no
+pub fn confirm(question: &str) -> bool {
+    eprint!("{question} [y/N] ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+// asks which side of a merge conflict to keep, anything but t/theirs keeps ours
+pub fn keep_theirs(question: &str) -> bool {
+    eprint!("{question} [o]urs/[t]heirs ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "t" | "theirs")
+}
+
 pub fn print_otp_with_progress(code: &str, rem: u64, quiet: bool) {
     if !quiet {
         let bar_width = 20;
@@ -24,3 +48,31 @@ pub fn print_otp_with_progress(code: &str, rem: u64, quiet: bool) {
 
     let _ = io::stdout().flush();
 }
+
+// formats a Unix timestamp as UTC without pulling in a date crate
+pub fn format_timestamp(secs: u64) -> String {
+    if secs == 0 {
+        return "-".to_string();
+    }
+
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // civil from days, see https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,215 @@
+use crate::integrity;
+use crate::models::{Codex, Header, Record};
+use crate::storage::{self, Storage, Transaction};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A codex loaded once and indexed by alias. Lookups and changes work in
+/// memory, `save` persists the pending changes in one storage transaction.
+pub struct Vault {
+    store: Box<dyn Storage>,
+    codex: Codex,
+    // parsed codex.lines, None where a line cannot be parsed
+    records: Vec<Option<Record>>,
+    // alias => index of its first line
+    index: HashMap<String, usize>,
+    pending: Vec<Change>,
+    empty_on_load: bool,
+    read_only_on_load: bool,
+}
+
+// replayed on save against backends that change single records, like SQLite
+enum Change {
+    Insert(Record),
+    Update(String, Record),
+    Delete(String),
+    Header(Option<Header>),
+    Replace(Codex),
+}
+
+impl Vault {
+    /// Opens the codex at path with the matching backend and loads it.
+    /// Fails when a sealed codex lost its seal.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let vault = Self::open_unchecked(path)?;
+        integrity::check_seal_present(path, vault.codex())?;
+        Ok(vault)
+    }
+
+    /// Like open, but loads a codex that lost its seal, e.g. to seal it again.
+    pub fn open_unchecked(path: &Path) -> Result<Self, String> {
+        let store = storage::open(path)?;
+        let codex = store.load()?;
+        let empty_on_load = codex.header.is_none() && codex.lines.iter().all(|l| l.trim().is_empty());
+        let read_only_on_load = codex.header.as_ref().is_some_and(|h| h.read_only);
+
+        let mut vault = Self {
+            store,
+            codex: Codex::default(),
+            records: Vec::new(),
+            index: HashMap::new(),
+            pending: Vec::new(),
+            empty_on_load,
+            read_only_on_load,
+        };
+        vault.load_codex(codex);
+        Ok(vault)
+    }
+
+    fn load_codex(&mut self, codex: Codex) {
+        self.records = codex.lines.iter().map(|l| Record::from_line(l)).collect();
+        self.codex = codex;
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, r) in self.records.iter().enumerate() {
+            if let Some(r) = r {
+                self.index.entry(r.alias.clone()).or_insert(i);
+            }
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.store.kind()
+    }
+
+    /// The codex including every change made so far.
+    pub fn codex(&self) -> &Codex {
+        &self.codex
+    }
+
+    /// True when the codex had neither header nor records when it was opened.
+    pub fn was_empty(&self) -> bool {
+        self.empty_on_load
+    }
+
+    /// True when the codex was locked with `hermes lock` when it was opened.
+    pub fn was_read_only(&self) -> bool {
+        self.read_only_on_load
+    }
+
+    pub fn get(&self, alias: &str) -> Option<&Record> {
+        self.index.get(alias).and_then(|&i| self.records[i].as_ref())
+    }
+
+    pub fn contains(&self, alias: &str) -> bool {
+        self.index.contains_key(alias)
+    }
+
+    /// Every parseable record in codex order.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter().flatten()
+    }
+
+    pub fn insert(&mut self, record: Record) -> Result<(), String> {
+        if self.contains(&record.alias) {
+            return Err(format!("Error: Alias '{}' already exists.", record.alias));
+        }
+
+        self.index.insert(record.alias.clone(), self.records.len());
+        self.codex.lines.push(record.to_string());
+        self.records.push(Some(record.clone()));
+        self.pending.push(Change::Insert(record));
+        Ok(())
+    }
+
+    /// Replaces the record stored under alias in place, keeping its line
+    /// position. False if there is no such record.
+    pub fn update(&mut self, alias: &str, record: Record) -> bool {
+        let Some(&i) = self.index.get(alias) else {
+            return false;
+        };
+
+        if record.alias != alias {
+            self.index.remove(alias);
+            self.index.insert(record.alias.clone(), i);
+        }
+        self.codex.lines[i] = record.to_string();
+        self.records[i] = Some(record.clone());
+        self.pending.push(Change::Update(alias.to_string(), record));
+        true
+    }
+
+    /// Renames a record in place, created_at and position stay the same.
+    /// The rename counts as a change of the record.
+    pub fn rename(&mut self, old_alias: &str, new_alias: &str) -> Result<(), String> {
+        if self.contains(new_alias) {
+            return Err(format!("Alias '{new_alias}' already exists."));
+        }
+        let mut record = self.get(old_alias)
+            .cloned()
+            .ok_or_else(|| format!("Alias '{old_alias}' not found."))?;
+
+        record.alias = new_alias.to_string();
+        record.touch();
+        self.update(old_alias, record);
+        Ok(())
+    }
+
+    /// Drops outgoing secrets whose grace period ended before now.
+    pub fn expire_previous(&mut self, now: u64) {
+        let expired: Vec<Record> = self.records()
+            .filter(|r| r.previous.is_some() && r.active_previous(now).is_none())
+            .cloned()
+            .collect();
+        for mut record in expired {
+            record.previous = None;
+            self.update(&record.alias.clone(), record);
+        }
+    }
+
+    /// Removes every line holding alias, false if there was none.
+    pub fn remove(&mut self, alias: &str) -> bool {
+        if !self.contains(alias) {
+            return false;
+        }
+
+        // unparseable lines are kept untouched
+        let keep: Vec<bool> = self.records
+            .iter()
+            .map(|r| r.as_ref().is_none_or(|r| r.alias != alias))
+            .collect();
+        let mut flags = keep.iter();
+        self.codex.lines.retain(|_| *flags.next().unwrap_or(&true));
+        let mut flags = keep.iter();
+        self.records.retain(|_| *flags.next().unwrap_or(&true));
+
+        self.reindex();
+        self.pending.push(Change::Delete(alias.to_string()));
+        true
+    }
+
+    pub fn set_header(&mut self, header: Option<Header>) {
+        self.codex.header = header.clone();
+        self.pending.push(Change::Header(header));
+    }
+
+    /// Replaces the whole codex, for rewrites like migrate or repair.
+    pub fn replace(&mut self, codex: Codex) {
+        self.pending.push(Change::Replace(codex.clone()));
+        self.load_codex(codex);
+    }
+
+    /// Persists every pending change in a single storage transaction. A
+    /// backend that rewrites the whole codex gets the result in one go.
+    pub fn save(&mut self) -> Result<(), String> {
+        let pending = std::mem::take(&mut self.pending);
+        let (codex, whole) = (&self.codex, self.store.rewrites_whole());
+        self.store.transaction(&mut |txn| match whole {
+            true => txn.replace_all(codex.clone()),
+            false => pending.iter().try_for_each(|change| apply(txn, change)),
+        })
+    }
+}
+
+fn apply(txn: &mut dyn Transaction, change: &Change) -> Result<(), String> {
+    match change {
+        Change::Insert(record) => txn.insert(record),
+        Change::Update(alias, record) => txn.update(alias, record).map(|_| ()),
+        Change::Delete(alias) => txn.delete(alias).map(|_| ()),
+        Change::Header(header) => txn.set_header(header.clone()),
+        Change::Replace(codex) => txn.replace_all(codex.clone()),
+    }
+}
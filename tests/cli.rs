@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::{cargo::cargo_bin_cmd, Command};
 use predicates::prelude::*;
 use tempfile::NamedTempFile;
 
@@ -8,15 +8,27 @@ const PASSWORD: &str = "password";
 
 /// helper fn hermes pointing to a temp file
 fn hermes(path: &std::path::Path) -> Command {
-    let mut cmd = Command::cargo_bin("hermes").expect("binary exists");
+    let mut cmd = cargo_bin_cmd!("hermes");
     cmd.arg("--path").arg(path);
+    // keep the developer's own config, e.g. read_only, out of the tests
+    cmd.env("HERMES_CONFIG", path.with_extension("config.toml"));
     cmd
 }
 
+/// helper fn to write a raw codex the way hermes would: owner-only
+fn write_raw_codex(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    std::fs::write(path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
 #[test]
 fn fail_run_with_no_args() -> Result<(), Box<dyn std::error::Error>> {
-    Command::cargo_bin("hermes")
-        .expect("binary exists")
+    cargo_bin_cmd!("hermes")
         .assert()
         .failure()
         .code(2)
@@ -30,8 +42,8 @@ fn fail_run_with_no_args() -> Result<(), Box<dyn std::error::Error>> {
 fn fail_add_missing_args() -> Result<(), Box<dyn std::error::Error>> {
     let file = NamedTempFile::new()?;
     
-    // 'add' fails without -a and -c
-    Command::cargo_bin("hermes")?
+    // 'add' fails without -a, -c is optional
+    cargo_bin_cmd!("hermes")
         .arg("--path")
         .arg(file.path())
         .arg("add")
@@ -50,13 +62,13 @@ fn add_remove_isolated_flow() -> Result<(), Box<dyn std::error::Error>> {
 
     hermes(path)
         .arg("add")
-        .args(&["-a", ALIAS, "-c", CODE, "--password", PASSWORD])
+        .args(["-a", ALIAS, "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     hermes(path)
         .arg("remove")
-        .args(&["-a", ALIAS])
+        .args(["-a", ALIAS])
         .assert()
         .success()
         .stdout(predicate::str::contains(format!("Record for {} removed.", ALIAS)));
@@ -70,24 +82,26 @@ fn add_update_remove_isolated_flow() -> Result<(), Box<dyn std::error::Error>> {
     let path = file.path();
     let alias = "test_update";
     let stdout_removed = format!("Record for {alias} removed.");
+    let stdout_updated = format!("Record for '{alias}' successfully updated.");
 
     hermes(path)
         .arg("add")
-        .args(&["-a", alias, "-c", CODE, "--password", PASSWORD])
+        .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
         .assert()
         .success()
         .stdout(predicate::str::is_match("[0-9]{6}")?);
 
     hermes(path)
         .arg("update")
-        .args(&["-a", alias, "-c", CODE, "--password", PASSWORD])
+        .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
         .assert()
         .success()
-        .stdout(predicate::str::contains(&stdout_removed));
+        .stdout(predicate::str::contains(stdout_updated))
+        .stdout(predicate::str::is_match("[0-9]{6}")?);
 
     hermes(path)
         .arg("remove")
-        .args(&["-a", alias])
+        .args(["-a", alias])
         .assert()
         .success()
         .stdout(predicate::str::contains(stdout_removed));
@@ -103,20 +117,20 @@ fn rename_alias_isolated_flow() -> Result<(), Box<dyn std::error::Error>> {
     // add two initial records
     hermes(path)
         .arg("add")
-        .args(&["-a", "github", "-c", CODE, "--password", PASSWORD])
+        .args(["-a", "github", "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     hermes(path)
         .arg("add")
-        .args(&["-a", "google", "-c", CODE, "--password", PASSWORD])
+        .args(["-a", "google", "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     // rename: github -> gh
     hermes(path)
         .arg("rename")
-        .args(&["github", "gh"])
+        .args(["github", "gh"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Successfully renamed"));
@@ -124,16 +138,16 @@ fn rename_alias_isolated_flow() -> Result<(), Box<dyn std::error::Error>> {
     // verify: new alias exists, old alias is gone
     hermes(path)
         .arg("ls")
-        .args(&["-a", "gh"])
-        .args(&["--password", PASSWORD])
+        .args(["-a", "gh"])
+        .args(["--password", PASSWORD])
         .assert()
         .success()
         .stdout(predicate::str::is_match(r"^\d{6}")?);
 
     hermes(path)
         .arg("ls")
-        .args(&["-a", "github"])
-        .args(&["--password", PASSWORD])
+        .args(["-a", "github"])
+        .args(["--password", PASSWORD])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Alias not found"));
@@ -141,7 +155,7 @@ fn rename_alias_isolated_flow() -> Result<(), Box<dyn std::error::Error>> {
     // collision Check: try to rename 'gh' to 'google' (exists)
     hermes(path)
         .arg("rename")
-        .args(&["gh", "google"])
+        .args(["gh", "google"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("already exists"));
@@ -157,27 +171,27 @@ fn ls_partial_search_isolated() -> Result<(), Box<dyn std::error::Error>> {
     // add multiple records with similar prefixes
     hermes(path)
         .arg("add")
-        .args(&["-a", "google", "-c", CODE, "--password", PASSWORD])
+        .args(["-a", "google", "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     hermes(path)
         .arg("add")
-        .args(&["-a", "goodreads", "-c", CODE, "--password", PASSWORD])
+        .args(["-a", "goodreads", "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     hermes(path)
         .arg("add")
-        .args(&["-a", "github", "-c", CODE, "--password", PASSWORD])
+        .args(["-a", "github", "-c", CODE, "--password", PASSWORD])
         .assert()
         .success();
 
     // test partial search: "goo" should return google and goodreads only
     hermes(path)
         .arg("ls")
-        .args(&["-a", "goo"])
-        .args(&["--password", PASSWORD])
+        .args(["-a", "goo"])
+        .args(["--password", PASSWORD])
         .assert()
         .success()
         .stdout(predicate::str::contains("google"))
@@ -187,8 +201,8 @@ fn ls_partial_search_isolated() -> Result<(), Box<dyn std::error::Error>> {
     // test non-matching search
     hermes(path)
         .arg("ls")
-        .args(&["-a", "no_match"])
-        .args(&["--password", PASSWORD])
+        .args(["-a", "no_match"])
+        .args(["--password", PASSWORD])
         .assert()
         .failure(); 
 
@@ -205,7 +219,7 @@ fn ls_json_format_isolated() -> Result<(), Box<dyn std::error::Error>> {
     for (alias, code) in &entries {
         hermes(path)
             .arg("add")
-            .args(&["-a", alias, "-c", code, "--password", PASSWORD])
+            .args(["-a", alias, "-c", code, "--password", PASSWORD])
             .assert()
             .success();
     }
@@ -213,8 +227,8 @@ fn ls_json_format_isolated() -> Result<(), Box<dyn std::error::Error>> {
     // ls with JSON format
     let output = hermes(path)
         .arg("ls")
-        .args(&["--password", PASSWORD])
-        .args(&["--format", "json"])
+        .args(["--password", PASSWORD])
+        .args(["--format", "json"])
         .output()?;
 
     // parse the actual JSON
@@ -237,3 +251,2169 @@ fn ls_json_format_isolated() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn add_code_from_stdin_isolated() -> Result<(), Box<dyn std::error::Error>> {
+    let file = NamedTempFile::new()?;
+    let path = file.path();
+
+    // code omitted => read from piped stdin
+    hermes(path)
+        .arg("add")
+        .args(["-a", ALIAS, "--password", PASSWORD])
+        .write_stdin(format!("{CODE}\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("[0-9]{6}")?);
+
+    hermes(path)
+        .arg("update")
+        .args(["-a", ALIAS, "--password", PASSWORD])
+        .write_stdin(CODE)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("successfully updated"));
+
+    // a taken alias fails before stdin is read
+    hermes(path)
+        .arg("add")
+        .args(["-a", ALIAS, "--password", PASSWORD])
+        .write_stdin("not base32!")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    // stdin content still has to be valid base32
+    hermes(path)
+        .arg("update")
+        .args(["-a", ALIAS, "--password", PASSWORD])
+        .write_stdin("not base32!")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid Base32"));
+
+    // empty stdin is refused
+    hermes(path)
+        .arg("add")
+        .args(["-a", "other", "--password", PASSWORD])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No code provided"));
+
+    Ok(())
+}
+
+#[test]
+fn mutations_rewrite_codex_atomically() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    for alias in ["github", "google"] {
+        hermes(&path)
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+
+    hermes(&path)
+        .arg("update")
+        .args(["-a", "github", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .arg("rename")
+        .args(["google", "gmail"])
+        .assert()
+        .success();
+
+    // header line, then one JSON record per line, nothing lost or merged in between
+    let content = std::fs::read_to_string(&path)?;
+    let aliases: Vec<String> = content
+        .lines()
+        .skip(1)
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["alias"].to_string())
+        .collect();
+    assert_eq!(aliases.len(), 2);
+    assert!(aliases.contains(&"\"github\"".to_string()));
+    assert!(aliases.contains(&"\"gmail\"".to_string()));
+
+    // no temp files left next to the codex
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().contains(".tmp."))
+        .collect();
+    assert!(leftovers.is_empty(), "temp files left behind: {leftovers:?}");
+
+    // an emptied codex is just the header, no blank record line
+    for alias in ["github", "gmail"] {
+        hermes(&path).args(["remove", "-a", alias]).assert().success();
+    }
+    assert_eq!(std::fs::read_to_string(&path)?.lines().count(), 1);
+    hermes(&path)
+        .arg("repair")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No unparseable lines found."));
+
+    Ok(())
+}
+
+#[test]
+fn locked_codex_is_reported_with_pid() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    hermes(&path)
+        .arg("add")
+        .args(["-a", ALIAS, "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+
+    // simulate another hermes holding the exclusive lock
+    let lock_path = dir.path().join("codex.lock");
+    let mut lock = std::fs::OpenOptions::new().write(true).open(&lock_path)?;
+    lock.lock()?;
+    std::io::Write::write_all(&mut lock, b"4242")?;
+
+    hermes(&path)
+        .env("HERMES_LOCK_TIMEOUT", "0")
+        .arg("remove")
+        .args(["-a", ALIAS])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Codex is locked by PID 4242"));
+
+    hermes(&path)
+        .env("HERMES_LOCK_TIMEOUT", "0")
+        .arg("ls")
+        .args(["--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("locked"));
+
+    // a shared lock lets readers through but still blocks writers
+    lock.unlock()?;
+    lock.lock_shared()?;
+
+    hermes(&path)
+        .env("HERMES_LOCK_TIMEOUT", "0")
+        .arg("ls")
+        .args(["--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .env("HERMES_LOCK_TIMEOUT", "0")
+        .arg("rename")
+        .args([ALIAS, "other"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("locked"));
+
+    lock.unlock()?;
+
+    hermes(&path)
+        .arg("remove")
+        .args(["-a", ALIAS])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn codex_files_are_private() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("hermes").join("codex");
+    let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+
+    for alias in ["github", "google"] {
+        hermes(&path)
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+
+    let backups = path.parent().unwrap().join("backups");
+    assert_eq!(mode(path.parent().unwrap()), 0o700);
+    assert_eq!(mode(&path), 0o600);
+    assert_eq!(mode(&backups), 0o700);
+    for entry in std::fs::read_dir(&backups)? {
+        assert_eq!(mode(&entry?.path()), 0o600);
+    }
+
+    // world readable codex is refused unless overridden
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+
+    hermes(&path)
+        .arg("ls")
+        .args(["--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Refusing"))
+        .stderr(predicate::str::contains("mode 644"));
+
+    hermes(&path)
+        .arg("remove")
+        .args(["-a", "google", "--allow-insecure-permissions"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Warning"));
+
+    // the rewrite restores private permissions
+    assert_eq!(mode(&path), 0o600);
+
+    // the backup of an older version, foo.bak of foo.db, is checked too
+    let db = path.with_file_name("foo.db");
+    hermes(&db).args(["add", "-a", "github", "-c", CODE, "-u"]).assert().success();
+    let legacy = path.with_file_name("foo.bak");
+    std::fs::copy(&db, &legacy)?;
+    std::fs::set_permissions(&legacy, std::fs::Permissions::from_mode(0o640))?;
+    hermes(&db)
+        .args(["ls", "-u"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("foo.bak (mode 640)"));
+
+    Ok(())
+}
+
+#[test]
+fn backup_rotation_list_restore_prune() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    for alias in ["a1", "a2", "a3", "a4"] {
+        hermes(&path)
+            .env("HERMES_BACKUP_RETENTION", "2")
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+
+    // 3 backups were taken (first add had nothing to back up), 2 are kept
+    let backups: Vec<_> = std::fs::read_dir(dir.path().join("backups"))?.collect();
+    assert_eq!(backups.len(), 2);
+
+    let output = hermes(&path).args(["backup", "list"]).output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("Records"));
+    assert!(stdout.contains("UTC"));
+
+    // newest first: the codex before a4 (3 records), then before a3 (2 records)
+    let ids: Vec<(&str, &str)> = stdout
+        .lines()
+        .skip(2)
+        .map(|l| {
+            let cols: Vec<&str> = l.split('|').map(str::trim).collect();
+            (cols[0], cols[2])
+        })
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[0].1, "3");
+    assert_eq!(ids[1].1, "2");
+
+    hermes(&path)
+        .args(["backup", "restore", ids[1].0])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("restored"));
+
+    // header + 2 records
+    let content = std::fs::read_to_string(&path)?;
+    assert_eq!(content.lines().count(), 3);
+
+    hermes(&path)
+        .args(["backup", "restore", "does-not-exist"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No backup with id"));
+
+    hermes(&path)
+        .args(["backup", "prune", "--keep", "1"])
+        .assert()
+        .success();
+
+    let backups: Vec<_> = std::fs::read_dir(dir.path().join("backups"))?.collect();
+    assert_eq!(backups.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn backup_retention_zero_and_legacy_backups() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    for alias in ["a1", "a2", "a3"] {
+        hermes(&path)
+            .env("HERMES_BACKUP_RETENTION", "0")
+            .args(["add", "-a", alias, "-c", CODE, "-u"])
+            .assert()
+            .success();
+    }
+    // the backup just taken survives
+    let backups: Vec<_> = std::fs::read_dir(dir.path().join("backups"))?.collect();
+    assert_eq!(backups.len(), 1);
+
+    // backups of older versions next to the codex are listed and restorable
+    write_raw_codex(&dir.path().join("codex.bak"), &format!("old:{CODE}:1:sha1\n"))?;
+    write_raw_codex(&dir.path().join("codex.1600000000.bak"), &format!("older:{CODE}:1:sha1\n"))?;
+    hermes(&path)
+        .args(["backup", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("legacy "))
+        .stdout(predicate::str::contains("legacy-1600000000"));
+    hermes(&path).args(["backup", "restore", "legacy-1600000000"]).assert().success();
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("older"));
+
+    Ok(())
+}
+
+#[test]
+fn sealed_codex_detects_tampering() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    for alias in ["github", "google", "gitlab"] {
+        hermes(&path)
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+
+    hermes(&path)
+        .args(["seal", "--password", "wrong"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Password does not decrypt"));
+
+    hermes(&path)
+        .args(["seal", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Codex sealed."));
+
+    // mutations keep the seal valid
+    hermes(&path)
+        .args(["remove", "-a", "gitlab", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("google"));
+
+    // reorder the record lines behind hermes' back
+    let content = std::fs::read_to_string(&path)?;
+    let mut lines: Vec<&str> = content.lines().collect();
+    assert!(lines[0].starts_with("{\"hermes\""));
+    lines.swap(1, 2);
+    std::fs::write(&path, lines.join("\n") + "\n")?;
+
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Integrity check failed"));
+
+    hermes(&path)
+        .args(["rename", "github", "gh", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Integrity check failed"));
+
+    // deliberate edit => re-seal
+    hermes(&path)
+        .args(["seal", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    // dropping a record is detected as well
+    let content = std::fs::read_to_string(&path)?;
+    let kept: Vec<&str> = content.lines().take(2).collect();
+    std::fs::write(&path, kept.join("\n") + "\n")?;
+
+    hermes(&path)
+        .args(["ls", "-a", "github", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Integrity check failed"));
+
+    Ok(())
+}
+
+#[test]
+fn sealed_codex_refuses_missing_header() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    for alias in ["x", "y"] {
+        hermes(&path)
+            .args(["add", "-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+    hermes(&path).args(["seal", "--password", PASSWORD]).assert().success();
+
+    // drop the header line, then a record, the way an attacker would
+    let content = std::fs::read_to_string(&path)?;
+    let lines: Vec<&str> = content.lines().skip(1).filter(|l| !l.contains(r#""alias":"x""#)).collect();
+    std::fs::write(&path, lines.join("\n") + "\n")?;
+
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("seal is missing"));
+    hermes(&path)
+        .args(["add", "-a", "z", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("seal is missing"));
+
+    // a deliberate edit is sealed again
+    hermes(&path).args(["seal", "--password", PASSWORD]).assert().success();
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("y"));
+
+    Ok(())
+}
+
+#[test]
+fn sealed_codex_refuses_tampered_journal_and_trash() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let journal = dir.path().join("codex.journal");
+    let trash = dir.path().join("codex.trash");
+    for (alias, code) in [("gh", CODE), ("bank", "JBSWY3DPEHPK3PXP")] {
+        hermes(&path)
+            .args(["add", "-a", alias, "-c", code, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+    hermes(&path).args(["seal", "--password", PASSWORD]).assert().success();
+    let content = std::fs::read_to_string(&path)?;
+    let gh: serde_json::Value = serde_json::from_str(content.lines().nth(1).unwrap())?;
+
+    // swap gh's secret into bank's trashed copy
+    hermes(&path).args(["remove", "-a", "bank", "--password", PASSWORD]).assert().success();
+    let mut item: serde_json::Value = serde_json::from_str(std::fs::read_to_string(&trash)?.trim())?;
+    item["record"]["secret"] = gh["secret"].clone();
+    std::fs::write(&trash, format!("{item}\n"))?;
+    hermes(&path)
+        .args(["trash", "restore", "bank", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("trashed record for 'bank' was modified"));
+
+    // untouched, undo and redo work under the seal
+    hermes(&path).args(["undo", "--password", PASSWORD]).assert().success();
+    hermes(&path).args(["redo", "--password", PASSWORD]).assert().success();
+
+    // and into bank's journal entry
+    let mut log: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&journal)?)?;
+    let last = log["entries"].as_array().unwrap().len() - 1;
+    log["entries"][last]["before"]["secret"] = gh["secret"].clone();
+    std::fs::write(&journal, log.to_string())?;
+    hermes(&path)
+        .args(["undo", "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("journal was modified outside hermes"));
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("bank").not());
+
+    // the next change starts a fresh journal instead of MACing the tampered one
+    hermes(&path)
+        .args(["add", "-a", "x", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("journal failed its integrity check"));
+    hermes(&path)
+        .args(["undo", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undid: add x"));
+
+    Ok(())
+}
+
+#[test]
+fn migrate_runs_versioned_chain() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    // legacy colon-separated line mixed with a JSON line, no header
+    let json = format!(
+        r#"{{"alias":"google","secret":"{CODE}","is_unencrypted":true,"algorithm":"sha1","created_at":1}}"#
+    );
+    write_raw_codex(&path, &format!("github:{CODE}:1:sha1\n{json}\n"))?;
+
+    hermes(&path)
+        .arg("migrate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("v0 -> v1"))
+        .stdout(predicate::str::contains("v1 -> v2"))
+        .stdout(predicate::str::contains("migrated 2 records to format version 2"));
+
+    let content = std::fs::read_to_string(&path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 3);
+    let header: serde_json::Value = serde_json::from_str(lines[0])?;
+    assert_eq!(header["hermes"]["version"], 2);
+    assert!(lines[1].contains("\"alias\":\"github\""));
+
+    hermes(&path)
+        .arg("migrate")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already at format version 2"));
+
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github"))
+        .stdout(predicate::str::contains("google"));
+
+    // a codex from a newer hermes can be read but is never written
+    let newer = content.replacen("\"version\":2", "\"version\":99", 1);
+    write_raw_codex(&path, &newer)?;
+
+    hermes(&path)
+        .args(["ls", "-u", "-a", "github"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["rename", "github", "gh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("newer than this hermes supports"));
+
+    hermes(&path)
+        .arg("migrate")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("newer than this hermes supports"));
+
+    assert_eq!(std::fs::read_to_string(&path)?, newer);
+
+    Ok(())
+}
+
+#[test]
+fn corrupt_lines_are_reported_and_quarantined() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    let record = |alias: &str| {
+        format!(
+            r#"{{"alias":"{alias}","secret":"{CODE}","is_unencrypted":true,"algorithm":"sha1","created_at":1}}"#
+        )
+    };
+    // legacy first line, so migrate has to run the v0 -> v1 rewrite
+    let content = format!(
+        "github:{CODE}:1:sha1\ngarbage\n{}\n{{\"alias\": broken\n{}\n",
+        record("google"),
+        record("gitlab")
+    );
+    write_raw_codex(&path, &content)?;
+
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("gitlab"))
+        .stderr(predicate::str::contains("line 2 cannot be parsed"))
+        .stderr(predicate::str::contains("line 4 cannot be parsed: invalid JSON"));
+
+    // migrating legacy lines would drop them, so it refuses
+    hermes(&path)
+        .arg("migrate")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 2"))
+        .stderr(predicate::str::contains("hermes repair"));
+
+    // other mutations keep them untouched
+    hermes(&path)
+        .args(["remove", "-a", "google"])
+        .assert()
+        .success();
+    let content = std::fs::read_to_string(&path)?;
+    assert!(content.contains("garbage"));
+    assert!(content.contains("{\"alias\": broken"));
+
+    hermes(&path)
+        .arg("repair")
+        .write_stdin("n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Aborted"));
+
+    hermes(&path)
+        .arg("repair")
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved 2 line(s)"));
+
+    let quarantine = std::fs::read_to_string(dir.path().join("codex.quarantine"))?;
+    assert_eq!(quarantine, "garbage\n{\"alias\": broken\n");
+
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("cannot be parsed").not());
+
+    hermes(&path)
+        .args(["repair", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No unparseable lines"));
+
+    Ok(())
+}
+
+#[test]
+fn sqlite_backend_flow() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex.db");
+
+    for alias in ["github", "google"] {
+        hermes(&path)
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+
+    let magic = std::fs::read(&path)?;
+    assert!(magic.starts_with(b"SQLite format 3\0"));
+
+    hermes(&path)
+        .args(["update", "-a", "github", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["rename", "google", "gmail"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["add", "-a", "gmail", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+    // found through the alias index before the code is even read
+    hermes(&path)
+        .args(["add", "-a", "gmail", "--password", PASSWORD])
+        .write_stdin("not base32!")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    hermes(&path)
+        .args(["ls", "-a", "gmail", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^\d{6}")?);
+
+    hermes(&path)
+        .args(["remove", "-a", "github"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("gmail"))
+        .stdout(predicate::str::contains("github").not());
+
+    // backups of a database are databases as well
+    hermes(&path)
+        .args(["backup", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("| 2"));
+
+    Ok(())
+}
+
+#[test]
+fn convert_between_file_and_sqlite() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let text = dir.path().join("codex");
+    let db = dir.path().join("codex.sqlite");
+    let back = dir.path().join("codex.txt");
+
+    for alias in ["github", "google"] {
+        hermes(&text)
+            .arg("add")
+            .args(["-a", alias, "-c", CODE, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+    hermes(&text)
+        .args(["seal", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&text)
+        .arg("convert")
+        .arg(&db)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Converted 2 records from file to sqlite"));
+
+    // the seal survives the conversion
+    hermes(&db)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github"));
+
+    hermes(&db)
+        .args(["remove", "-a", "github", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&db)
+        .arg("convert")
+        .arg(&back)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Converted 1 records from sqlite to file"));
+
+    let content = std::fs::read_to_string(&back)?;
+    assert!(content.starts_with("{\"hermes\""));
+    assert!(content.contains("google"));
+    assert!(!content.contains("github"));
+
+    hermes(&back)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success();
+
+    // never overwrite an existing codex
+    hermes(&text)
+        .arg("convert")
+        .arg(&db)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    Ok(())
+}
+
+/// helper fn to run plain git in tests
+fn git(dir: &std::path::Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("git runs");
+    assert!(output.status.success(), "git {args:?}: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn git_autocommit_and_sync() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let remote = dir.path().join("remote.git");
+    let a = dir.path().join("a").join("codex");
+    let b = dir.path().join("b").join("codex");
+    std::fs::create_dir(&remote)?;
+    git(&remote, &["init", "--quiet", "--bare"]);
+
+    // sync needs the opt-in
+    hermes(&a)
+        .arg("add")
+        .args(["-a", "github", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+    hermes(&a)
+        .arg("sync")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("hermes git init"));
+
+    hermes(&a)
+        .args(["git", "init", "--remote"])
+        .arg(&remote)
+        .assert()
+        .success();
+    hermes(&a).arg("sync").assert().success();
+
+    let log = git(a.parent().unwrap(), &["log", "--format=%s"]);
+    assert!(log.contains("hermes: track codex"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let gitignore = a.parent().unwrap().join(".gitignore");
+        assert_eq!(std::fs::metadata(gitignore)?.permissions().mode() & 0o777, 0o600);
+    }
+
+    // second machine starts from a clone of the remote
+    git(dir.path(), &["clone", "--quiet", remote.to_str().unwrap(), "b"]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&b, std::fs::Permissions::from_mode(0o600))?;
+    }
+    hermes(&b).args(["git", "init"]).assert().success();
+
+    hermes(&a)
+        .arg("add")
+        .args(["-a", "gitlab", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+    hermes(&a).arg("sync").assert().success();
+
+    hermes(&b)
+        .args(["remove", "-a", "github"])
+        .assert()
+        .success();
+    hermes(&b)
+        .arg("add")
+        .args(["-a", "google", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+
+    let log = git(b.parent().unwrap(), &["log", "--format=%s"]);
+    assert!(log.contains("hermes: remove github"));
+    assert!(log.contains("hermes: add google"));
+
+    // a sync that fails to save leaves no half-done merge behind
+    let backups = b.parent().unwrap().join("backups");
+    std::fs::rename(&backups, b.with_file_name("backups.off"))?;
+    std::fs::write(&backups, "")?;
+    hermes(&b).arg("sync").assert().failure();
+    assert!(!b.parent().unwrap().join(".git").join("MERGE_HEAD").exists());
+    std::fs::remove_file(&backups)?;
+    std::fs::rename(b.with_file_name("backups.off"), &backups)?;
+
+    // diverged => record level merge: github deleted on b, gitlab added on a
+    hermes(&b)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Merged 2 records"));
+
+    hermes(&a)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Fast-forwarded"));
+
+    for path in [&a, &b] {
+        hermes(path)
+            .args(["ls", "--password", PASSWORD])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("google"))
+            .stdout(predicate::str::contains("gitlab"))
+            .stdout(predicate::str::contains("github").not());
+    }
+    let parents = git(b.parent().unwrap(), &["log", "-1", "--format=%p"]);
+    assert_eq!(parents.split_whitespace().count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn diff_and_merge_across_passwords_and_formats() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+    let other_code = "JBSWY3DPEHPK3PXP";
+
+    for (alias, code) in [("github", CODE), ("google", CODE)] {
+        hermes(&ours)
+            .arg("add")
+            .args(["-a", alias, "-c", code, "--password", PASSWORD])
+            .assert()
+            .success();
+    }
+    for (alias, code) in [("google", other_code), ("gitlab", CODE)] {
+        hermes(&other)
+            .arg("add")
+            .args(["-a", alias, "-c", code, "--password", "other"])
+            .assert()
+            .success();
+    }
+    // a legacy line in the middle of JSON lines
+    let content = std::fs::read_to_string(&other)?;
+    write_raw_codex(&other, &format!("{content}aws:{other_code}:1:sha1\n"))?;
+
+    hermes(&ours)
+        .arg("diff")
+        .arg(&other)
+        .args(["--password", PASSWORD])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--other-password"));
+
+    hermes(&ours)
+        .arg("diff")
+        .arg(&other)
+        .args(["--password", PASSWORD, "--other-password", "other"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("- github"))
+        .stdout(predicate::str::contains("~ google"))
+        .stdout(predicate::str::contains("+ gitlab"))
+        .stdout(predicate::str::contains("+ aws"))
+        .stdout(predicate::str::contains("2 added, 1 removed, 1 changed."));
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["--strategy", "ours", "--password", PASSWORD, "--other-password", "other"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 added, 0 replaced, 1 kept."));
+
+    // merged secrets are encrypted with our password
+    hermes(&ours)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("gitlab"))
+        .stdout(predicate::str::contains("aws"))
+        .stdout(predicate::str::contains("Error").not());
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["--strategy", "theirs", "--password", PASSWORD, "--other-password", "other"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 added, 1 replaced, 0 kept."));
+
+    hermes(&ours)
+        .arg("diff")
+        .arg(&other)
+        .args(["--password", PASSWORD, "--other-password", "other"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 added, 1 removed, 0 changed."));
+
+    Ok(())
+}
+
+#[test]
+fn merge_newest_takes_updated_record() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+    let other_code = "JBSWY3DPEHPK3PXP";
+
+    hermes(&ours).args(["add", "-a", "x", "-c", CODE, "-u"]).assert().success();
+    std::fs::copy(&ours, &other)?;
+    // same created_at on both sides, only the update tells them apart
+    hermes(&other).args(["update", "-a", "x", "-c", other_code, "-u"]).assert().success();
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["-s", "newest"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 added, 1 replaced, 0 kept."));
+    assert!(std::fs::read_to_string(&ours)?.contains(&format!(r#""secret":"{other_code}""#)));
+
+    Ok(())
+}
+
+#[test]
+fn diff_and_merge_see_notes_and_tags() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+    let both = ["--password", "pa", "--other-password", "pb"];
+
+    hermes(&ours).args(["add", "-a", "x", "-c", CODE, "--password", "pa"]).assert().success();
+    hermes(&other).args(["add", "-a", "x", "-c", CODE, "--password", "pb"]).assert().success();
+    hermes(&ours)
+        .arg("diff")
+        .arg(&other)
+        .args(both)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No differences."));
+
+    // same secret, only the note and a tag are new on their side
+    hermes(&other)
+        .args(["note", "set", "x", "recovery codes in the safe", "--password", "pb"])
+        .assert()
+        .success();
+    hermes(&other).args(["tag", "add", "x", "finance", "--password", "pb"]).assert().success();
+    hermes(&ours)
+        .arg("diff")
+        .arg(&other)
+        .args(both)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("~ x"))
+        .stdout(predicate::str::contains("0 added, 0 removed, 1 changed."));
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(both)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 added, 1 replaced, 0 kept."));
+    hermes(&ours)
+        .args(["note", "show", "x", "--password", "pa"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("recovery codes in the safe"));
+    hermes(&ours)
+        .arg("tags")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("finance"));
+
+    Ok(())
+}
+
+#[test]
+fn merge_reencrypts_notes() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+
+    hermes(&ours).args(["add", "-a", "x", "-c", CODE, "--password", "pa"]).assert().success();
+    hermes(&other).args(["add", "-a", "y", "-c", CODE, "--password", "pb"]).assert().success();
+    hermes(&other)
+        .args(["note", "set", "y", "recovery codes in the safe", "--password", "pb"])
+        .assert()
+        .success();
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["--password", "pa", "--other-password", "pb"])
+        .assert()
+        .success();
+    hermes(&ours)
+        .args(["note", "show", "y", "--password", "pa"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("recovery codes in the safe"));
+
+    Ok(())
+}
+
+#[test]
+fn merge_reencrypts_history() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+
+    hermes(&ours).args(["add", "-a", "x", "-c", CODE, "--password", "pa"]).assert().success();
+    hermes(&other).args(["add", "-a", "y", "-c", CODE, "--password", "pb"]).assert().success();
+    hermes(&other)
+        .args(["update", "-a", "y", "-c", "JBSWY3DPEHPK3PXP", "--password", "pb"])
+        .assert()
+        .success();
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["--password", "pa", "--other-password", "pb"])
+        .assert()
+        .success();
+    hermes(&ours)
+        .args(["history", "y", "--password", "pa"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Error").not());
+    hermes(&ours).args(["rollback", "y", "--password", "pa"]).assert().success();
+    hermes(&ours)
+        .args(["ls", "--password", "pa"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Error").not());
+
+    Ok(())
+}
+
+#[test]
+fn merge_reencrypts_previous_secret() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+
+    hermes(&ours).args(["add", "-a", "x", "-c", CODE, "--password", "pa"]).assert().success();
+    hermes(&other).args(["add", "-a", "y", "-c", CODE, "--password", "pb"]).assert().success();
+    hermes(&other)
+        .args(["update", "-a", "y", "-c", "JBSWY3DPEHPK3PXP", "--grace", "24", "--password", "pb"])
+        .assert()
+        .success();
+
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["--password", "pa", "--other-password", "pb"])
+        .assert()
+        .success();
+    hermes(&ours)
+        .args(["ls", "--password", "pa"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Previous"))
+        .stdout(predicate::str::contains("Error").not());
+
+    Ok(())
+}
+
+#[test]
+fn named_vaults_select_codex() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    let vaulted = || {
+        let mut cmd = cargo_bin_cmd!("hermes");
+        cmd.env("HERMES_CONFIG", &config)
+            .env_remove("HERMES_PATH")
+            .env_remove("HERMES_VAULT");
+        cmd
+    };
+
+    for name in ["work", "personal"] {
+        vaulted()
+            .args(["vault", "add", name])
+            .arg(dir.path().join(name))
+            .assert()
+            .success();
+    }
+    vaulted()
+        .args(["vault", "add", "work", "elsewhere"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    vaulted()
+        .args(["--vault", "work", "add", "-a", "github", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+    vaulted()
+        .env("HERMES_VAULT", "personal")
+        .args(["add", "-a", "google", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+    assert!(dir.path().join("work").exists());
+    assert!(dir.path().join("personal").exists());
+
+    vaulted()
+        .args(["vault", "default", "work"])
+        .assert()
+        .success();
+    vaulted()
+        .args(["vault", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("work (default)"))
+        .stdout(predicate::str::contains("personal"));
+    vaulted()
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github"))
+        .stdout(predicate::str::contains("google").not());
+
+    vaulted()
+        .args(["ls", "--all-vaults", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Vault"))
+        .stdout(predicate::str::is_match(r"work\s+\| github")?)
+        .stdout(predicate::str::is_match(r"personal\s+\| google")?);
+
+    vaulted()
+        .args(["vault", "remove", "work"])
+        .assert()
+        .success();
+    vaulted()
+        .args(["--vault", "work", "ls"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No vault named 'work'"));
+
+    Ok(())
+}
+
+#[test]
+fn update_and_rename_keep_order_and_created_at() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let records = |path: &std::path::Path| -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .skip(1) // header
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    };
+
+    // created_at far in the past, so a rewrite with the current time shows
+    let mut content = String::from("{\"hermes\":{\"version\":2}}\n");
+    for alias in ["first", "second", "third"] {
+        content.push_str(&format!(
+            "{{\"alias\":\"{alias}\",\"secret\":\"{CODE}\",\"is_unencrypted\":true,\"algorithm\":\"sha1\",\"created_at\":1000}}\n"
+        ));
+    }
+    write_raw_codex(&path, &content)?;
+
+    hermes(&path)
+        .args(["update", "-a", "first", "-c", "JBSWY3DPEHPK3PXP", "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["rename", "second", "renamed"])
+        .assert()
+        .success();
+
+    let after = records(&path);
+    let aliases: Vec<&str> = after.iter().map(|r| r["alias"].as_str().unwrap()).collect();
+    assert_eq!(aliases, ["first", "renamed", "third"]);
+    assert_eq!(after[0]["secret"], "JBSWY3DPEHPK3PXP");
+    assert!(after.iter().all(|r| r["created_at"] == 1000));
+
+    Ok(())
+}
+
+#[test]
+fn undo_redo_journal() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    hermes(&path)
+        .arg("undo")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Codex not found"));
+
+    for alias in ["github", "google"] {
+        hermes(&path)
+            .args(["add", "-a", alias, "-c", CODE, "-u"])
+            .assert()
+            .success();
+    }
+    hermes(&path)
+        .args(["remove", "-a", "github"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .arg("undo")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undid: remove github"));
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github"));
+
+    hermes(&path)
+        .arg("redo")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Redid: remove github"));
+    hermes(&path)
+        .arg("redo")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Nothing to redo."));
+
+    hermes(&path)
+        .args(["rename", "google", "gmail"])
+        .assert()
+        .success();
+    hermes(&path)
+        .arg("undo")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undid: rename google -> gmail"));
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("google"))
+        .stdout(predicate::str::contains("gmail").not());
+
+    // an edit outside hermes makes the journal stale
+    let content = std::fs::read_to_string(&path)?;
+    write_raw_codex(&path, &format!("{content}aws:{CODE}:1:sha1\n"))?;
+    hermes(&path)
+        .arg("undo")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("changed outside hermes"));
+
+    Ok(())
+}
+
+#[test]
+fn trash_restore_and_purge() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    for alias in ["github", "google", "gitlab"] {
+        hermes(&path)
+            .args(["add", "-a", alias, "-c", CODE, "-u"])
+            .assert()
+            .success();
+    }
+    hermes(&path)
+        .args(["remove", "-a", "github"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved to trash"));
+    hermes(&path)
+        .args(["remove", "-a", "google", "--permanent"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved to trash").not());
+    hermes(&path)
+        .args(["remove", "-a", "gitlab"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["trash", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github"))
+        .stdout(predicate::str::contains("gitlab"))
+        .stdout(predicate::str::contains("google").not());
+
+    // the alias was taken again in the meantime
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["trash", "restore", "github"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("restored as 'github-restored'"));
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("github-restored"));
+
+    hermes(&path)
+        .args(["trash", "purge", "--older-than", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 0 record(s) from trash, 1 left."));
+    hermes(&path)
+        .args(["trash", "purge", "--older-than", "999999999999999"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 0 record(s) from trash, 1 left."));
+    hermes(&path)
+        .args(["trash", "purge"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 1 record(s) from trash, 0 left."));
+    hermes(&path)
+        .args(["trash", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Trash is empty."));
+
+    Ok(())
+}
+
+#[test]
+fn secret_history_and_rollback() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let record = |path: &std::path::Path| -> serde_json::Value {
+        let content = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str(content.lines().nth(1).unwrap()).unwrap()
+    };
+
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["history", "github"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No previous secrets"));
+
+    for code in ["JBSWY3DPEHPK3PXP", "GEZDGNBVGY3TQOJQ"] {
+        hermes(&path)
+            .args(["update", "-a", "github", "-c", code, "-u"])
+            .assert()
+            .success();
+    }
+    hermes(&path)
+        .args(["history", "github"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("current"))
+        .stdout(predicate::str::is_match(r"(?m)^2\s+\|")?);
+
+    hermes(&path)
+        .args(["rollback", "github", "--to", "2"])
+        .assert()
+        .success();
+    let after = record(&path);
+    assert_eq!(after["secret"], CODE);
+    assert_eq!(after["history"].as_array().unwrap().len(), 2);
+    assert_eq!(after["history"][1]["secret"], "GEZDGNBVGY3TQOJQ");
+
+    hermes(&path)
+        .args(["rollback", "github", "--to", "3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no version 3"));
+
+    // the cap applies on the next update
+    hermes(&path)
+        .env("HERMES_HISTORY_LIMIT", "1")
+        .args(["update", "-a", "github", "-c", "JBSWY3DPEHPK3PXP", "-u"])
+        .assert()
+        .success();
+    assert_eq!(record(&path)["history"].as_array().unwrap().len(), 1);
+
+    hermes(&path)
+        .args(["history", "github", "--purge"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 1 previous secret(s)"));
+    assert!(record(&path).get("history").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn rotation_grace_keeps_previous_code() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let record = |path: &std::path::Path| -> serde_json::Value {
+        let content = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str(content.lines().nth(1).unwrap()).unwrap()
+    };
+
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["update", "-a", "github", "-c", "JBSWY3DPEHPK3PXP", "-u", "--grace", "24"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stays listed for 24 hour(s)"));
+    assert_eq!(record(&path)["previous"]["secret"], CODE);
+
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Previous"));
+    let output = hermes(&path).args(["ls", "-u", "--format", "json"]).output()?;
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert!(list[0]["previous_otp"].is_string());
+
+    hermes(&path)
+        .args(["confirm", "github"])
+        .assert()
+        .success();
+    assert!(record(&path).get("previous").is_none());
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Previous").not());
+
+    // an absurd grace period saturates instead of overflowing
+    hermes(&path)
+        .args(["update", "-a", "github", "-c", "JBSWY3DPEHPK3PXP", "-u", "--grace", "9999999999999999"])
+        .assert()
+        .success();
+    assert_eq!(record(&path)["previous"]["until"], u64::MAX);
+    hermes(&path).args(["confirm", "github"]).assert().success();
+
+    // a rollback ends the grace period of the rotation it undoes
+    hermes(&path)
+        .args(["update", "-a", "github", "-c", CODE, "-u", "--grace", "24"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["rollback", "github"])
+        .assert()
+        .success();
+    assert!(record(&path).get("previous").is_none());
+    assert_eq!(record(&path)["secret"], "JBSWY3DPEHPK3PXP");
+
+    // an ended grace period is hidden at once and dropped on the next write
+    hermes(&path)
+        .args(["update", "-a", "github", "-c", CODE, "-u", "--grace", "1"])
+        .assert()
+        .success();
+    let content = std::fs::read_to_string(&path)?;
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let mut expired = record(&path);
+    expired["previous"]["until"] = serde_json::json!(1);
+    lines[1] = expired.to_string();
+    write_raw_codex(&path, &(lines.join("\n") + "\n"))?;
+
+    let output = hermes(&path).args(["ls", "-u", "--format", "json"]).output()?;
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert!(list[0]["previous_otp"].is_null());
+    hermes(&path)
+        .args(["add", "-a", "gitlab", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    assert!(record(&path).get("previous").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn audit_log_chains_and_detects_tampering() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let log = dir.path().join("codex.audit");
+
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["audit", "enable"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["add", "-a", "gitlab", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success();
+
+    // enable, add gitlab, ls github and gitlab
+    hermes(&path)
+        .args(["audit", "verify"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("intact, 4 entries"));
+    hermes(&path)
+        .args(["audit", "show", "--alias", "gitlab"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)\| add\s+\| gitlab$")?)
+        .stdout(predicate::str::is_match(r"(?m)\| ls\s+\| gitlab$")?)
+        .stdout(predicate::str::contains("github").not());
+
+    let content = std::fs::read_to_string(&log)?;
+    std::fs::write(&log, content.replacen("\"ls\"", "\"add\"", 1))?;
+    hermes(&path)
+        .args(["audit", "verify"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 3: content does not match"));
+
+    let lines: Vec<&str> = content.lines().collect();
+    std::fs::write(&log, lines[..3].join("\n") + "\n")?;
+    hermes(&path)
+        .args(["audit", "verify"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cut off"));
+
+    Ok(())
+}
+
+#[test]
+fn read_only_codex_and_locked_records() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["--read-only", "add", "-a", "gitlab", "-c", CODE, "-u"])
+        .assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains("read-only (--read-only)"));
+    hermes(&path)
+        .env("HERMES_READ_ONLY", "1")
+        .args(["rename", "github", "gh"])
+        .assert()
+        .failure()
+        .code(3);
+    hermes(&path)
+        .args(["--read-only", "ls", "-u"])
+        .assert()
+        .success();
+
+    // a locked codex refuses changes until it is unlocked
+    hermes(&path).args(["lock"]).assert().success();
+    for args in [vec!["migrate"], vec!["trash", "purge"], vec!["undo"]] {
+        hermes(&path)
+            .args(&args)
+            .assert()
+            .failure()
+            .code(3)
+            .stderr(predicate::str::contains("locked with `hermes lock`"));
+    }
+    hermes(&path).args(["compact", "--dry-run"]).assert().success();
+    hermes(&path).args(["unlock"]).assert().success();
+
+    hermes(&path).args(["lock", "github"]).assert().success();
+    for args in [
+        vec!["remove", "-a", "github"],
+        vec!["update", "-a", "github", "-c", CODE, "-u"],
+        vec!["rename", "github", "gh"],
+    ] {
+        hermes(&path)
+            .args(&args)
+            .assert()
+            .failure()
+            .code(4)
+            .stderr(predicate::str::contains("Record 'github' is locked"));
+    }
+    hermes(&path).args(["unlock", "github"]).assert().success();
+    hermes(&path)
+        .args(["rename", "github", "gh"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn config_defaults_and_precedence() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    let codex = dir.path().join("codex");
+    let configured = || {
+        let mut cmd = cargo_bin_cmd!("hermes");
+        cmd.env("HERMES_CONFIG", &config)
+            .env_remove("HERMES_PATH")
+            .env_remove("HERMES_VAULT")
+            .env_remove("HERMES_FORMAT")
+            .env_remove("HERMES_COLUMNS");
+        cmd
+    };
+
+    configured().args(["config", "set", "path"]).arg(&codex).assert().success();
+    configured()
+        .args(["add", "-a", "github", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    assert!(codex.exists());
+
+    configured().args(["config", "set", "format", "json"]).assert().success();
+    configured()
+        .args(["config", "get", "format"])
+        .assert()
+        .success()
+        .stdout("json\n");
+    configured()
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"alias\": \"github\""));
+
+    // env beats the config file, flags beat both
+    configured()
+        .env("HERMES_FORMAT", "table")
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Alias"));
+    configured()
+        .env("HERMES_FORMAT", "table")
+        .args(["ls", "-u", "-f", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"otp\""));
+
+    configured().args(["config", "unset", "format"]).assert().success();
+    configured().args(["config", "set", "columns", "alias,created"]).assert().success();
+    configured()
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^Alias\s+\| Created")?)
+        .stdout(predicate::str::contains("Rem").not());
+
+    configured()
+        .args(["config", "set", "columns", "alias,bogus"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid columns"));
+    configured()
+        .args(["config", "get", "colour"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown config key 'colour'"));
+    configured()
+        .args(["config", "get", "quiet"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'quiet' is not set"));
+
+    // edits that do not parse leave the config alone
+    configured()
+        .env("EDITOR", "echo 'quiet = maybe' >>")
+        .args(["config", "edit"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid config, nothing changed"));
+    configured()
+        .env("EDITOR", "echo 'quiet = true' >>")
+        .args(["config", "edit"])
+        .assert()
+        .success();
+    configured()
+        .args(["config", "get", "quiet"])
+        .assert()
+        .success()
+        .stdout("true\n");
+
+    Ok(())
+}
+
+#[test]
+fn colon_aliases_only_in_json_codex() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+
+    // a new codex is written with the current header, so ':' is fine
+    let fresh = dir.path().join("fresh");
+    hermes(&fresh)
+        .args(["add", "-a", "GitHub:me@example.com", "-c", CODE, "-u"])
+        .assert()
+        .success();
+    hermes(&fresh)
+        .args(["ls", "-u", "-a", "GitHub:me"])
+        .assert()
+        .success();
+
+    let legacy = dir.path().join("legacy");
+    write_raw_codex(&legacy, &format!("github:{CODE}:1:sha1\n"))?;
+    hermes(&legacy)
+        .args(["add", "-a", "Google:me", "-c", CODE, "-u"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("once the codex is fully JSON"));
+    hermes(&legacy)
+        .args(["rename", "github", "GitHub:me"])
+        .assert()
+        .failure();
+
+    hermes(&legacy).args(["migrate"]).assert().success();
+    hermes(&legacy)
+        .args(["rename", "github", "GitHub:me"])
+        .assert()
+        .success();
+    hermes(&legacy)
+        .args(["add", "-a", "Google:me", "-c", CODE, "-u"])
+        .assert()
+        .success();
+
+    // a legacy line added by hand makes the codex mixed again
+    let mut content = std::fs::read_to_string(&legacy)?;
+    content.push_str(&format!("gitlab:{CODE}:1:sha1\n"));
+    write_raw_codex(&legacy, &content)?;
+    hermes(&legacy)
+        .args(["add", "-a", "Slack:me", "-c", CODE, "-u"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn compact_rewrites_codex_canonically() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let json = |alias: &str, secret: &str, created: u64| {
+        format!(r#"{{"alias":"{alias}","secret":"{secret}","is_unencrypted":true,"algorithm":"sha1","created_at":{created}}}"#)
+    };
+    let content = [
+        json("zoom", CODE, 3),
+        String::new(),
+        format!("bank:{}:1:sha1", CODE.to_lowercase()),
+        json("github", &format!("{}==", CODE.to_lowercase()), 5),
+        json("zoom", CODE, 3),
+        "not a record".to_string(),
+    ]
+    .join("\n");
+    write_raw_codex(&path, &(content.clone() + "\n"))?;
+
+    hermes(&path)
+        .args(["compact", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 blank line(s) removed"))
+        .stdout(predicate::str::contains("1 legacy line(s) converted to JSON"))
+        .stdout(predicate::str::contains("2 secret(s) normalized"))
+        .stdout(predicate::str::contains("1 duplicate record(s) merged"))
+        .stdout(predicate::str::contains("records sorted"))
+        .stdout(predicate::str::contains("Dry run"))
+        .stderr(predicate::str::contains("1 unparseable line(s)"));
+    assert_eq!(std::fs::read_to_string(&path)?, content + "\n");
+
+    hermes(&path).args(["compact"]).assert().success();
+    let lines: Vec<String> = std::fs::read_to_string(&path)?.lines().map(String::from).collect();
+    let aliases: Vec<String> = lines[..3]
+        .iter()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["alias"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(aliases, ["bank", "github", "zoom"]);
+    assert!(lines[1].contains(&format!("\"secret\":\"{CODE}\"")));
+    assert_eq!(lines[3], "not a record");
+
+    hermes(&path)
+        .args(["compact", "--sort", "created"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("records sorted"));
+    hermes(&path)
+        .args(["compact", "--sort", "created"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already compact"));
+
+    Ok(())
+}
+
+#[test]
+fn issuer_and_account_from_flags_and_otpauth() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let uri = format!("otpauth://totp/ACME%20Co:me%40example.com?secret={CODE}&issuer=ACME%20Co");
+
+    hermes(&path)
+        .args(["add", "-a", "acme", "-c", &uri, "-u"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["add", "-a", "github", "-c", CODE, "-u", "--issuer", "GitHub", "--account", "work"])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Issuer"))
+        .stdout(predicate::str::contains("ACME Co"))
+        .stdout(predicate::str::contains("me@example.com"));
+    hermes(&path)
+        .args(["ls", "-u", "-f", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""issuer": "GitHub""#))
+        .stdout(predicate::str::contains(r#""account": "work""#));
+
+    // ls -a matches the issuer too, and a metadata-only update keeps the secret
+    hermes(&path)
+        .args(["update", "-a", "github", "--account", "personal"])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["ls", "-u", "-a", "acme co", "-f", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""alias": "acme""#))
+        .stdout(predicate::str::contains("github").not());
+    let content = std::fs::read_to_string(&path)?;
+    assert!(content.contains(r#""account":"personal""#));
+    assert!(content.contains(&format!(r#""secret":"{CODE}""#)));
+
+    hermes(&path)
+        .args(["add", "-a", "sha256", "-c", &format!("otpauth://totp/x?secret={CODE}&algorithm=SHA256"), "-u"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(CODE).not());
+
+    Ok(())
+}
+
+#[test]
+fn tags_filter_and_count_records() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    for alias in ["aws", "bank", "blog"] {
+        hermes(&path).args(["add", "-a", alias, "-c", CODE, "-u"]).assert().success();
+    }
+    for (alias, tag) in [("aws", "infra"), ("aws", "Work"), ("bank", "finance"), ("bank", "work")] {
+        hermes(&path).args(["tag", "add", alias, tag]).assert().success();
+    }
+    hermes(&path)
+        .args(["tag", "add", "blog", "two words"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid tag"));
+
+    // all tags must match, or one of them with --any-tag
+    hermes(&path)
+        .args(["ls", "-u", "--tag", "work", "--tag", "infra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("aws"))
+        .stdout(predicate::str::contains("bank").not());
+    hermes(&path)
+        .args(["ls", "-u", "--tag", "finance", "--tag", "infra", "--any-tag"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("aws"))
+        .stdout(predicate::str::contains("bank"))
+        .stdout(predicate::str::contains("blog").not());
+    hermes(&path)
+        .args(["ls", "-u", "--tag", "finance", "-f", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""finance","#));
+
+    hermes(&path)
+        .args(["tags"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"work\s+\| 2")?)
+        .stdout(predicate::str::is_match(r"infra\s+\| 1")?);
+
+    hermes(&path).args(["tag", "remove", "aws", "infra"]).assert().success();
+    hermes(&path)
+        .args(["tag", "remove", "aws", "infra"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not tagged"));
+    hermes(&path).args(["undo"]).assert().success();
+    hermes(&path)
+        .args(["ls", "-u", "--tag", "infra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("aws"));
+
+    Ok(())
+}
+
+#[test]
+fn notes_are_encrypted_and_hidden_by_default() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    let note = "backup phone +1 555 0100";
+    hermes(&path)
+        .args(["add", "-a", "bank", "-c", CODE, "--password", PASSWORD])
+        .assert()
+        .success();
+
+    hermes(&path)
+        .args(["note", "set", "bank", note, "--password", "wrong"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Wrong password"));
+    hermes(&path)
+        .args(["note", "set", "bank", "--password", PASSWORD])
+        .write_stdin(format!("{note}\nrecovery codes in the safe\n"))
+        .assert()
+        .success();
+    assert!(!std::fs::read_to_string(&path)?.contains("backup phone"));
+
+    hermes(&path)
+        .args(["note", "show", "bank", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("{note}\nrecovery codes in the safe")));
+
+    // off screen unless asked for
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(note).not());
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD, "--show-notes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("{note} / recovery codes")));
+    hermes(&path)
+        .args(["ls", "--password", PASSWORD, "-f", "json", "--show-notes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""notes": "backup phone"#));
+
+    hermes(&path)
+        .args(["note", "clear", "bank", "--password", PASSWORD])
+        .assert()
+        .success();
+    hermes(&path)
+        .args(["note", "show", "bank", "--password", PASSWORD])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No note"));
+
+    Ok(())
+}
+
+#[test]
+fn locked_records_refuse_merge_undo_and_sync() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let other_code = "JBSWY3DPEHPK3PXP";
+    let locked_line = |path: &std::path::Path| -> Result<String, std::io::Error> {
+        Ok(std::fs::read_to_string(path)?.lines().find(|l| l.contains(r#""alias":"z""#)).unwrap_or_default().to_string())
+    };
+
+    // merge
+    let ours = dir.path().join("codex");
+    let other = dir.path().join("other");
+    hermes(&ours).args(["add", "-a", "z", "-c", CODE, "-u"]).assert().success();
+    hermes(&other).args(["add", "-a", "z", "-c", other_code, "-u"]).assert().success();
+    hermes(&ours).args(["lock", "z"]).assert().success();
+    let before = locked_line(&ours)?;
+    hermes(&ours)
+        .arg("merge")
+        .arg(&other)
+        .args(["-s", "theirs"])
+        .assert()
+        .failure()
+        .code(4);
+    assert_eq!(locked_line(&ours)?, before);
+
+    // undo, of the lock too
+    hermes(&ours).args(["undo"]).assert().failure().code(4);
+    assert_eq!(locked_line(&ours)?, before);
+
+    // sync
+    let remote = dir.path().join("remote.git");
+    let a = dir.path().join("a").join("codex");
+    let b = dir.path().join("b").join("codex");
+    std::fs::create_dir(&remote)?;
+    git(&remote, &["init", "--quiet", "--bare"]);
+    hermes(&a).args(["add", "-a", "z", "-c", CODE, "-u"]).assert().success();
+    hermes(&a).args(["git", "init", "--remote"]).arg(&remote).assert().success();
+    hermes(&a).args(["lock", "z"]).assert().success();
+    hermes(&a).arg("sync").assert().success();
+
+    git(dir.path(), &["clone", "--quiet", remote.to_str().unwrap(), "b"]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&b, std::fs::Permissions::from_mode(0o600))?;
+    }
+    hermes(&b).args(["git", "init"]).assert().success();
+    hermes(&b).args(["unlock", "z"]).assert().success();
+    hermes(&b).args(["update", "-a", "z", "-c", other_code, "-u"]).assert().success();
+    hermes(&b).arg("sync").assert().success();
+
+    let before = locked_line(&a)?;
+    hermes(&a).arg("sync").assert().failure().code(4);
+    assert_eq!(locked_line(&a)?, before);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn audit_failure_blocks_displays_and_changes() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    hermes(&path).args(["add", "-a", "x", "-c", CODE, "-u"]).assert().success();
+    hermes(&path).args(["audit", "enable"]).assert().success();
+
+    // an unwritable log, a directory in its place
+    let log = dir.path().join("codex.audit");
+    std::fs::remove_file(&log)?;
+    std::fs::create_dir(&log)?;
+    std::fs::set_permissions(&log, std::fs::Permissions::from_mode(0o700))?;
+    let before = std::fs::read_to_string(&path)?;
+
+    hermes(&path).args(["ls", "-u"]).assert().failure();
+    hermes(&path)
+        .args(["add", "-a", "y", "-c", CODE, "-u"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not changed"));
+    hermes(&path).args(["remove", "-a", "x"]).assert().failure();
+    assert_eq!(std::fs::read_to_string(&path)?, before);
+
+    Ok(())
+}
+
+#[test]
+fn undo_remove_takes_record_out_of_trash() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("codex");
+    hermes(&path).args(["add", "-a", "x", "-c", CODE, "-u"]).assert().success();
+    hermes(&path).args(["remove", "-a", "x"]).assert().success();
+    hermes(&path).args(["undo"]).assert().success();
+
+    hermes(&path)
+        .args(["trash", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Trash is empty."));
+    hermes(&path).args(["trash", "restore", "x"]).assert().failure();
+    hermes(&path)
+        .args(["ls", "-u"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("x-restored").not());
+
+    // redoing the remove puts it back into the trash
+    hermes(&path).args(["redo"]).assert().success();
+    hermes(&path)
+        .args(["trash", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("x "));
+    hermes(&path).args(["trash", "restore", "x"]).assert().success();
+    hermes(&path).args(["ls", "-a", "x", "-u"]).assert().success();
+
+    Ok(())
+}